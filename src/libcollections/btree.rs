@@ -13,337 +13,5773 @@
 
 //! Simple implementation of a B-tree.
 
+use std::cmp::min;
+use std::default::Default;
+use std::fmt;
+use std::hash::Hash;
+use std::iter::{Peekable, Rev};
+use std::kinds::marker;
+use std::mem::{replace, size_of};
+use std::uint;
+use std::vec;
+use std::vec_ng::Vec;
+use std::vec_ng;
+
+use deque::Deque;
+use ringbuf::RingBuf;
+
+//The minimum degree used when the caller doesn't choose one.
+static DEFAULT_MIN_DEG: uint = 6;
+
 ///A B-tree contains a root node (which contains a vector of elements),
-///a length (the height of the tree), and lower and upper bounds on the
-///number of elements that a given node can contain.
+///a length (the number of elements), the height of the tree, the minimum
+///degree, which bounds how many elements a node can contain, and the
+///policy insert follows for a key that is already present.
 
 #[allow(missing_doc)]
 pub struct BTree<K, V> {
     priv root: Node<K, V>,
-    //priv len: uint,
-    //priv lower_bound: uint,
-    //priv upper_bound: uint
-    priv min_deg: uint
+    priv len: uint,
+    priv height: uint,
+    priv min_deg: uint,
+    priv dup_policy: DupPolicy
 }
 
-//A node contains a vector of elements (key-value pairs) as well as children, optionally.
+//A node contains a vector of elements (key-value pairs), and is either a
+//leaf or a branch with one more child than it has elements.  A branch also
+//caches the number of elements in its whole subtree.
 struct Node<K, V> {
-    elts: ~[Elt<K, V>],
-    children: Option<~[~Node<K,V>]>, // if Some(), a Branch
+    elts: Vec<Elt<K, V>>,
+    kind: NodeKind<K, V>
+}
+
+#[deriving(Clone)]
+enum NodeKind<K, V> {
+    Leaf,
+    Branch(Vec<~Node<K, V>>, uint)
 }
 
 //An Elt contains a key-value pair.
+#[deriving(Clone)]
 struct Elt<K, V> {
     key: K,
     value: V
 }
 
+///One endpoint of a range passed to range_bounds: the endpoint's key is
+///either part of the range, left out of it, or the range is open on that
+///side.
+pub enum Bound<T> {
+    Included(T),
+    Excluded(T),
+    Unbounded
+}
+
+///What insert does with a key that is already in the tree: replace the
+///stored value with the new one, or keep the stored value and hand the new
+///one back.  swap always replaces the value, whatever the policy.
+#[deriving(Clone, Eq, Show)]
+pub enum DupPolicy {
+    Overwrite,
+    KeepFirst
+}
+
+///A type that can stand in for a key of type K in lookups, such as &str
+///for ~str, so that a tree can be searched without building an owned key.
+pub trait BorrowKey<K> {
+    ///Returns the ordering of key relative to this one.  It must agree
+    ///with the ordering of the keys themselves.
+    fn compare_key(&self, key: &K) -> Ordering;
+}
+
+impl<'a> BorrowKey<~str> for &'a str {
+    fn compare_key(&self, key: &~str) -> Ordering {
+        key.as_slice().cmp(self)
+    }
+}
+
+impl<'a, T: TotalOrd> BorrowKey<~[T]> for &'a [T] {
+    fn compare_key(&self, key: &~[T]) -> Ordering {
+        key.as_slice().cmp(self)
+    }
+}
+
+//Returns the most elements a node of minimum degree md may hold,
+//2 * md - 1, failing rather than wrapping around if that overflows.
+fn max_elts_for(md: uint) -> uint {
+    match md.checked_mul(&2) {
+        Some(n) => n - 1,
+        None => fail!("BTree: a minimum degree of {} is too large", md)
+    }
+}
+
+//Returns the most elements a tree of minimum degree md may hold if it is
+//one level taller than a tree that holds at most cap: 2 * md subtrees of
+//cap elements each, plus the 2 * md - 1 elements of the new root.  Fails
+//rather than wrapping around if that overflows.
+fn next_level_cap_for(cap: uint, md: uint) -> uint {
+    let fanout = max_elts_for(md) + 1;
+    match cap.checked_add(&1).and_then(|n| n.checked_mul(&fanout)) {
+        Some(n) => n - 1,
+        None => fail!("BTree: a tree of minimum degree {} is too tall to count", md)
+    }
+}
+
+//Removes and returns the element at index i of v, moving the ones after
+//it down a place.  Fails if i is out of bounds.
+fn remove_at<T>(v: &mut Vec<T>, i: uint) -> T {
+    let len = v.len();
+    assert!(i < len, "BTree: index {} is out of bounds", i);
+    for j in range(i, len - 1) {
+        v.as_mut_slice().swap(j, j + 1);
+    }
+    v.pop().unwrap()
+}
+
+//Returns the fewest elements a node of minimum degree md other than the
+//root may hold.
+fn min_elts_for(md: uint) -> uint {
+    md - 1
+}
+
+///Returns the largest minimum degree for which a full node's elements
+///fit in node_byte_budget bytes, given the size of a key-value pair of
+///types K and V.  The result is never less than 2, so budgets smaller than
+///three pairs are exceeded, and never more than the largest degree a tree
+///accepts.  Zero-sized pairs fit any budget, so for them the default
+///degree is returned.
+pub fn optimal_degree_for<K, V>(node_byte_budget: uint) -> uint {
+    let elt_size = size_of::<Elt<K, V>>();
+    if elt_size == 0 {
+        return DEFAULT_MIN_DEG;
+    }
+    //A full node holds 2 * md - 1 elements, so md is half the elements
+    //rounded up, worked out without overflowing for the largest budgets.
+    let elts = node_byte_budget / elt_size;
+    let md = elts / 2 + elts % 2;
+    if md < 2 { 2 } else { min(md, uint::MAX / 2) }
+}
+
 impl<K: TotalOrd, V> BTree<K, V> {
 
     ///Returns new BTree with root node (leaf) and user-supplied lower bound
     ///The lower bound applies to every node except the root node.
+    ///Fails if the minimum degree is less than 2, or so large that a full
+    ///node's size overflows.
     pub fn new(k: K, v: V, md: uint) -> BTree<K, V> {
+        assert!(md >= 2, "BTree minimum degree must be at least 2");
+        max_elts_for(md);
         BTree {
-            root: Node {elts: ~[Elt {key: k, value: v}], children: None},
-            //len: 1,
-            //lower_bound: lb,
-            //upper_bound: 2 * lb
-            min_deg: md
-        }
-    }
-
-    pub fn insert(&mut self, k: K, v: V) {
-        //First, check to see if the root is full.
-        if self.root.elts.len() >= self.min_deg * 2 - 1 {
-            let mut new_root_elts = ~[];
-            let new_root_children = match self.root.children {
-                None => None,
-                Some(ref mut kids) => {
-                    let mut child_vec = ~[];
-                    for i in range(0, kids.len()) {
-                        child_vec.push(kids.pop().unwrap());
-                    }
-                    child_vec.reverse();
-                    Some(child_vec)
-                }
-            };
-            for i in range(0, self.root.elts.len()){
-                new_root_elts.push(self.root.elts.pop().unwrap());
+            root: Node {elts: vec!(Elt {key: k, value: v}), kind: Leaf},
+            len: 1,
+            height: 1,
+            min_deg: md,
+            dup_policy: Overwrite
+        }
+    }
+
+    ///Returns an empty BTree with the given minimum degree.
+    ///Fails if the minimum degree is less than 2, or so large that a full
+    ///node's size overflows.
+    pub fn with_degree(md: uint) -> BTree<K, V> {
+        BTree::with_dup_policy(md, Overwrite)
+    }
+
+    ///Returns an empty BTree with the given minimum degree, whose insert
+    ///follows policy when a key is already present.
+    ///Fails if the minimum degree is less than 2, or so large that a full
+    ///node's size overflows.
+    pub fn with_dup_policy(md: uint, policy: DupPolicy) -> BTree<K, V> {
+        assert!(md >= 2, "BTree minimum degree must be at least 2");
+        max_elts_for(md);
+        BTree {
+            root: Node {elts: Vec::new(), kind: Leaf},
+            len: 0,
+            height: 1,
+            min_deg: md,
+            dup_policy: policy
+        }
+    }
+
+    ///Returns what insert does with a key that is already present.
+    pub fn dup_policy(&self) -> DupPolicy {
+        self.dup_policy
+    }
+
+    //The most elements any node may hold.
+    fn max_elts(&self) -> uint {
+        max_elts_for(self.min_deg)
+    }
+
+    //The fewest elements any node but the root may hold.
+    fn min_elts(&self) -> uint {
+        min_elts_for(self.min_deg)
+    }
+
+    ///Returns an empty BTree whose full nodes hold about node_byte_budget
+    ///bytes of elements, using the degree from optimal_degree_for.
+    pub fn with_node_budget(node_byte_budget: uint) -> BTree<K, V> {
+        BTree::with_degree(optimal_degree_for::<K, V>(node_byte_budget))
+    }
+
+    ///Builds a tree from pairs that are already sorted by key, in linear
+    ///time.  Nodes are filled as close to capacity as the minimum degree
+    ///allows, so the tree has as few nodes as possible.
+    pub fn from_sorted(pairs: ~[(K, V)], md: uint) -> BTree<K, V> {
+        let mut tree = BTree::with_degree(md);
+        if cfg!(not(ndebug)) {
+            for i in range(1, pairs.len()) {
+                let (ref prev, _) = pairs[i - 1];
+                let (ref next, _) = pairs[i];
+                assert!(prev.cmp(next) == Less,
+                        "BTree::from_sorted: keys must be in strictly ascending order");
             }
-            new_root_elts.reverse();
-            let new_root = ~Node { elts: new_root_elts, children: new_root_children };
-            self.root = Node { elts: ~[], children: Some(~[new_root]) };
-            //self.root = Node {elts: ~[], children: Some(~[~self.root])};
-            self.root.split_child(0, self.min_deg * 2 - 1);
-            self.root.insert_nonfull(k, v, self.min_deg * 2 - 1);
         }
-        //If it is not full, call the helper method for a non-full Node.
-        else {
-            self.root.insert_nonfull(k, v, self.min_deg * 2 - 1);
+        let len = pairs.len();
+        if len == 0 {
+            return tree;
         }
+        //Find the shortest tree that can hold every pair.
+        let mut height = 1;
+        let mut capacity = max_elts_for(md);
+        while capacity < len {
+            capacity = next_level_cap_for(capacity, md);
+            height += 1;
+        }
+        tree.root = Node::build(&mut pairs.move_iter(), len, height, md, true);
+        tree.len = len;
+        tree.height = height;
+        tree
     }
-}
 
-impl<K: TotalOrd, V> Node<K, V> {
-    fn split_child(&mut self, i: uint, ub: uint) {
-        if self.children.get_ref()[i].elts.len() < ub { return; }
-        let mut new_elts_left = ~[];
-        let mut new_elts_right = ~[];
-        let new_node_left;
-        let new_node_right;
-        let mid_elt;
-        {
-            let child: &mut Node<K,V> = &mut *self.children.get_mut_ref()[i];
-            for j in range(0, child.elts.len() / 2) {
-                new_elts_right.push(child.elts.pop().unwrap());
-            }
-            new_elts_right.reverse();
-            mid_elt = child.elts.pop().unwrap();
-            for j in range(0, child.elts.len()) {
-                new_elts_left.push(child.elts.pop().unwrap());
-            }
-            new_elts_left.reverse();
-            let new_opt_grandchildren_right = match child.children {
-                None => None,
-                Some(ref mut gchild) => {
-                    let mut grandchildren = ~[];
-                    for j in range(0, gchild.len() / 2) {
-                        grandchildren.push(gchild.pop().unwrap());
-                    }
-                    grandchildren.reverse();
-                    Some(grandchildren)
+    ///Like from_sorted, but checks that the keys are in strictly ascending
+    ///order first, in every build.  If they are not, the error names the
+    ///first pair of positions that are out of order.
+    pub fn try_from_sorted(pairs: ~[(K, V)], md: uint) -> Result<BTree<K, V>, ~str> {
+        for i in range(1, pairs.len()) {
+            let (ref prev, _) = pairs[i - 1];
+            let (ref next, _) = pairs[i];
+            match prev.cmp(next) {
+                Less => {}
+                Equal => {
+                    return Err(format!("BTree::try_from_sorted: the keys at {} and {} are equal",
+                                       i - 1, i));
                 }
-            };
-            let new_opt_grandchildren_left = match child.children {
-                None => None,
-                Some(ref mut gchild) => {
-                    let mut grandchildren = ~[];
-                    for j in range(0, gchild.len()) {
-                        grandchildren.push(gchild.pop().unwrap());
-                    }
-                    grandchildren.reverse();
-                    Some(grandchildren)
+                Greater => {
+                    return Err(format!("BTree::try_from_sorted: the key at {} is less than \
+                                        the key at {}", i, i - 1));
                 }
+            }
+        }
+        Ok(BTree::from_sorted(pairs, md))
+    }
+
+    ///Like from_sorted, but the keys need only be in ascending order, not
+    ///strictly ascending: each run of equal keys is collapsed to its last
+    ///pair before the tree is built.
+    pub fn from_sorted_dedup(pairs: ~[(K, V)], md: uint) -> BTree<K, V> {
+        let mut deduped: ~[(K, V)] = vec::with_capacity(pairs.len());
+        for (k, v) in pairs.move_iter() {
+            let repeated = match deduped.last() {
+                None => false,
+                Some(&(ref last, _)) => last.cmp(&k) == Equal
             };
-            new_node_left = ~Node { elts: new_elts_left, children: new_opt_grandchildren_left };
-            new_node_right = ~Node { elts: new_elts_right, children: new_opt_grandchildren_right };
+            if repeated {
+                deduped.pop();
+            }
+            deduped.push((k, v));
         }
-        self.elts.insert(i, mid_elt);
-        self.children.get_mut_ref().insert(i, new_node_left);
-        self.children.get_mut_ref().insert(i + 1, new_node_right);
+        BTree::from_sorted(deduped, md)
     }
 
-    fn insert_nonfull(&mut self, k: K, v: V, ub: uint) {
-        match self.children {
-            //If we have no children, we are a Leaf and can insert here.
-            None => {
-                //Check the index returned by bsearch: is the key already there?
-                let mut index = self.bsearch_node(&k);
-                //Check to make sure the index is in bounds.
-                if self.elts.len() <= index {
-                    self.elts.push(Elt { key: k, value: v });
+    ///Builds a tree from an iterator of pairs that are already sorted by
+    ///key, without collecting them first.  Leaves are filled one at a time
+    ///and their parents grow as they fill, so beyond the finished nodes
+    ///only the rightmost node of each level is held.  Every node ends up
+    ///full except those down the right edge, which are topped up from
+    ///their left siblings once the iterator runs out.
+    pub fn from_sorted_iter<T: Iterator<(K, V)>>(iter: T, md: uint) -> BTree<K, V> {
+        let mut tree = BTree::with_degree(md);
+        let cap = tree.max_elts();
+        //The rightmost node of each level, from the leaves up.  Each of
+        //these branches is missing its last child, the node below it.
+        let mut spine = vec!(Node { elts: Vec::new(), kind: Leaf });
+        let mut iter = iter;
+        let mut len = 0;
+        for (k, v) in iter {
+            if cfg!(not(ndebug)) {
+                //The previous key is the last one in the lowest spine node
+                //that isn't empty, since it may have gone up a level.
+                let prev = spine.iter().find(|node| !node.elts.is_empty())
+                                .map(|node| &node.elts.get(node.elts.len() - 1).key);
+                assert!(prev.map_or(true, |prev| prev.cmp(&k) == Less),
+                        "BTree::from_sorted_iter: keys must be in strictly ascending order");
+            }
+            len += 1;
+            let elt = Elt { key: k, value: v };
+            if spine.get(0).elts.len() < cap {
+                spine.get_mut(0).elts.push(elt);
+                continue;
+            }
+            //The leaf is full, so the new element separates it from the
+            //next leaf, and goes up until it finds a node with room.  Each
+            //full node it passes is finished and replaced by an empty one.
+            let mut done = replace(spine.get_mut(0), Node { elts: Vec::new(), kind: Leaf });
+            let mut level = 1;
+            loop {
+                if level == spine.len() {
+                    spine.push(Node { elts: Vec::new(), kind: Branch(Vec::new(), 0) });
                 }
-                else {
-                    match self.elts[index].key.cmp(&k) {
-                        //If there is already a key at that index that matches
-                        //the one we want to add, just update the value.
-                        Equal => {
-                            self.elts[index].value = v;
-                        }
-                        //Check this: it should be Greater every time it's not Equal.
-                        _ => {
-                            self.elts.insert(index, Elt { key: k, value: v });
-                        }
-                    }
+                let node = spine.get_mut(level);
+                node.mut_kids().push(~done);
+                if node.elts.len() < cap {
+                    node.elts.push(elt);
+                    break;
                 }
-                //If we have no children, we're done here.
-                return;
+                done = replace(node, Node { elts: Vec::new(), kind: Branch(Vec::new(), 0) });
+                done.recount_size();
+                level += 1;
             }
-            Some(..) => {
-                let mut index = self.bsearch_node(&k);
-                self.split_child(index, ub);
+        }
+        //Hang each node of the spine under the one above it.
+        tree.len = len;
+        tree.height = spine.len();
+        let mut levels = spine.move_iter();
+        let mut root = levels.next().unwrap();
+        for parent in levels {
+            let mut parent = parent;
+            parent.mut_kids().push(~root);
+            parent.recount_size();
+            root = parent;
+        }
+        root.fill_right_edge(tree.min_elts());
+        tree.root = root;
+        tree
+    }
 
-                //First check to make sure index is in bounds.
-                if index < self.elts.len() {
+    ///Inserts a key-value pair into the tree.  If the key was already
+    ///present, its old value is replaced and returned, or, if the tree's
+    ///policy is KeepFirst, the old value stays and v is returned instead.
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        let policy = self.dup_policy;
+        self.insert_with_policy(k, v, policy)
+    }
 
-                    //Does the split cause us to change the index?  Check here.
-                    match self.elts[index].key.cmp(&k) {
-                        Greater => {
-                            index = index + 1;
-                        }
-                        //Index should stay the same and equal cases are handled by leaves.
-                        _ => {}
-                    }
+    ///Inserts a key-value pair, returning the value it displaced if the
+    ///key was already present.  Unlike insert, swap always replaces the
+    ///stored value, whatever the tree's DupPolicy.
+    pub fn swap(&mut self, k: K, v: V) -> Option<V> {
+        self.insert_with_policy(k, v, Overwrite)
+    }
+
+    //Inserts a pair, resolving a key that is already present by policy.
+    fn insert_with_policy(&mut self, k: K, v: V, policy: DupPolicy) -> Option<V> {
+        self.split_full_root();
+        let ub = self.max_elts();
+        let old = self.root.insert_nonfull(k, v, ub, policy);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    ///Inserts a key-value pair only if the key is not already present,
+    ///leaving any existing value as it is.  Returns true if the pair was
+    ///inserted.
+    pub fn insert_if_absent(&mut self, k: K, v: V) -> bool {
+        let (_, inserted) = self.find_or_insert_common(k, v, |v| v);
+        inserted
+    }
+
+    ///Returns a mutable reference to the value stored under the given key,
+    ///first inserting v if the key is not already present.
+    pub fn find_or_insert<'a>(&'a mut self, k: K, v: V) -> &'a mut V {
+        let (value, _) = self.find_or_insert_common(k, v, |v| v);
+        value
+    }
+
+    ///Returns a mutable reference to the value stored under the given key,
+    ///first inserting the value returned by f if the key is not already
+    ///present.  f is only called if the key is missing.
+    pub fn find_or_insert_with<'a>(&'a mut self, k: K, f: || -> V) -> &'a mut V {
+        let (value, _) = self.find_or_insert_common(k, (), |_| f());
+        value
+    }
+
+    ///Returns the entry for k, which is occupied if k is in the tree and
+    ///vacant otherwise, so that a value can be looked up and updated or
+    ///inserted without handling the two cases separately.  Full nodes on
+    ///the way down are split as for insert, so a vacant entry remembers
+    ///where its key goes and inserts without searching again.
+    pub fn entry<'a>(&'a mut self, k: K) -> Entry<'a, K, V> {
+        self.split_full_root();
+        let ub = self.max_elts();
+        let mut path = Vec::new();
+        if self.root.descend_to_insert(&k, ub, &mut path) {
+            Occupied(OccupiedEntry { value: self.root.value_at_path(path.as_slice()) })
+        } else {
+            Vacant(VacantEntry { tree: self, key: k, path: path })
+        }
+    }
+
+    //Looks up or inserts a key in a single descent, returning the value
+    //and whether it was inserted.
+    fn find_or_insert_common<'a, A>(&'a mut self, k: K, a: A,
+                                    not_found: |A| -> V) -> (&'a mut V, bool) {
+        self.split_full_root();
+        let ub = self.max_elts();
+        let (value, inserted) = self.root.find_or_insert(k, a, not_found, ub);
+        if inserted {
+            self.len += 1;
+        }
+        (value, inserted)
+    }
+
+    //If the root is full, move it down to be the only child of a new,
+    //empty root and split it, so the tree grows by one level.
+    fn split_full_root(&mut self) {
+        let ub = self.max_elts();
+        if self.root.elts.len() >= ub {
+            let old_root = replace(&mut self.root, Node { elts: Vec::new(), kind: Leaf });
+            let size = old_root.size();
+            self.root.kind = Branch(vec!(~old_root), size);
+            self.root.split_child(0, ub);
+            self.height += 1;
+        }
+    }
+
+    ///Returns a reference to the value stored under the given key,
+    ///or None if the key is not in the tree.
+    pub fn find<'a>(&'a self, k: &K) -> Option<&'a V> {
+        self.root.find(k).map(|elt| &elt.value)
+    }
+
+    ///Returns the key stored in the tree that is equal to k, along with
+    ///its value.  The stored key can differ from k in ways its ordering
+    ///ignores.
+    pub fn get_key_value<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)> {
+        self.root.find(k).map(|elt| (&elt.key, &elt.value))
+    }
+
+    ///Returns a mutable reference to the value stored under the given key,
+    ///or None if the key is not in the tree.
+    pub fn find_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+        self.root.find_mut(k)
+    }
+
+    ///Returns a reference to the value whose key f reports as Equal, or
+    ///None if there is none.  f compares a stored key against whatever is
+    ///being sought, so a tree can be searched by part of its keys.  It must
+    ///be consistent with the order of the keys: Less for keys below the
+    ///target, Greater for keys above it.
+    pub fn find_with<'a>(&'a self, f: |&K| -> Ordering) -> Option<&'a V> {
+        let mut node = &self.root;
+        loop {
+            let mut min = 0;
+            let mut max = node.elts.len();
+            while min < max {
+                let mid = min + (max - min) / 2;
+                match f(&node.elts.get(mid).key) {
+                    Less => { min = mid + 1; }
+                    _ => { max = mid; }
                 }
-                //Check to see if we need to split the child.
-                let child: &mut Node<K,V> = &mut *self.children.get_mut_ref()[index];
-                //Regardless of whether we split the child, we now move to that child.
-                //let child: &mut Node<K,V> = &mut *self.children.get_mut_ref()[index];
-                child.insert_nonfull(k, v, ub);
+            }
+            if min < node.elts.len() && f(&node.elts.get(min).key) == Equal {
+                return Some(&node.elts.get(min).value);
+            }
+            match node.kind {
+                Leaf => return None,
+                Branch(ref kids, _) => node = &**kids.get(min)
             }
         }
     }
 
-    ///Searches a node for an index at which to insert a new key.
-    fn bsearch_node(&self, k: &K) -> uint {
-        let mut min = 0;
-        let mut max = self.elts.len();
-        let mut mid = (min + max) / 2;
-        match self.elts[min].key.cmp(k) {
-            Greater => { return 0; }
-            _ => {}
+    ///Returns a reference to the value whose key matches q, a borrowed
+    ///form of the key, or None if there is none.
+    pub fn find_borrowed<'a, Q: BorrowKey<K>>(&'a self, q: &Q) -> Option<&'a V> {
+        self.find_with(|k| q.compare_key(k))
+    }
+
+    ///Returns true if the tree has a key matching q, a borrowed form of
+    ///the key.
+    pub fn contains_key_borrowed<Q: BorrowKey<K>>(&self, q: &Q) -> bool {
+        self.find_borrowed(q).is_some()
+    }
+
+    ///Returns a reference to the value stored under the given key, or
+    ///default if the key is not in the tree.
+    pub fn get_or<'a>(&'a self, k: &K, default: &'a V) -> &'a V {
+        self.find(k).unwrap_or(default)
+    }
+
+    ///Returns mutable references to the values stored under two different
+    ///keys at once, or None if either key is missing or the keys are equal.
+    pub fn find_pair_mut<'a>(&'a mut self, a: &K, b: &K) -> Option<(&'a mut V, &'a mut V)> {
+        if a.cmp(b) == Equal {
+            return None;
         }
-        match self.elts[max - 1].key.cmp(k) {
-            Less => { return max; }
-            _ => {}
+        let a_ptr = match self.find_mut(a) {
+            None => return None,
+            Some(v) => v as *mut V
+        };
+        let b_ptr = match self.find_mut(b) {
+            None => return None,
+            Some(v) => v as *mut V
+        };
+        //The keys differ, so the two values are stored in different
+        //elements and the references cannot overlap.
+        unsafe { Some((&mut *a_ptr, &mut *b_ptr)) }
+    }
+
+    ///Returns true if the tree contains a value for the given key.
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.find(k).is_some()
+    }
+
+    ///Removes the key from the tree, returning its value if it was present.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        self.remove_with(|key| key.cmp(k))
+    }
+
+    ///Removes the key matching q, a borrowed form of the key, returning
+    ///its value if it was present.
+    pub fn remove_borrowed<Q: BorrowKey<K>>(&mut self, q: &Q) -> Option<V> {
+        self.remove_with(|k| q.compare_key(k))
+    }
+
+    //Removes the key that f reports as Equal, as for find_with.
+    fn remove_with(&mut self, f: |&K| -> Ordering) -> Option<V> {
+        if self.root.elts.is_empty() {
+            return None;
         }
-        //println!("min is {} max is {}", min, max);
-        while max > min && min != mid && max != mid {
-            //println!("mid is {}", mid);
-            match self.elts[mid].key.cmp(k) {
-                Equal => {
-                    return mid;
+        let ret = self.root.remove_with(f, self.min_deg);
+        if ret.is_some() {
+            self.len -= 1;
+        }
+        self.shrink_empty_root();
+        ret
+    }
+
+    ///Removes the key from the tree, returning its value if it was present.
+    ///The same as remove, under the name MutableMap uses.
+    pub fn pop(&mut self, k: &K) -> Option<V> {
+        self.remove(k)
+    }
+
+    ///Removes and returns the entry with the smallest key.
+    pub fn pop_min(&mut self) -> Option<(K, V)> {
+        if self.root.elts.is_empty() {
+            return None;
+        }
+        let Elt { key: key, value: value } = self.root.remove_min(self.min_deg);
+        self.len -= 1;
+        self.shrink_empty_root();
+        Some((key, value))
+    }
+
+    ///Removes and returns the entry with the largest key.
+    pub fn pop_max(&mut self) -> Option<(K, V)> {
+        if self.root.elts.is_empty() {
+            return None;
+        }
+        let Elt { key: key, value: value } = self.root.remove_max(self.min_deg);
+        self.len -= 1;
+        self.shrink_empty_root();
+        Some((key, value))
+    }
+
+    //If the root has run out of elements, its only child becomes the new root.
+    fn shrink_empty_root(&mut self) {
+        if self.root.elts.is_empty() {
+            match replace(&mut self.root.kind, Leaf) {
+                Leaf => {}
+                Branch(kids, _) => {
+                    let ~new_root = kids.move_iter().next().unwrap();
+                    self.root = new_root;
+                    self.height -= 1;
                 }
-                Less => {
-                    max = mid;
+            }
+        }
+    }
+
+    ///Moves every entry whose key is greater than or equal to k into a new
+    ///tree with the same minimum degree, leaving the smaller keys in this
+    ///one.  Both trees are rebuilt from their sorted entries.
+    pub fn split_off(&mut self, k: &K) -> BTree<K, V> {
+        let md = self.min_deg;
+        let policy = self.dup_policy;
+        let left_len = self.rank(k);
+        let mut left = vec::with_capacity(left_len);
+        let mut right = vec::with_capacity(self.len - left_len);
+        let tree = replace(self, BTree::with_degree(md));
+        for (key, value) in tree.move_iter() {
+            if key.cmp(k) == Less {
+                left.push((key, value));
+            }
+            else {
+                right.push((key, value));
+            }
+        }
+        *self = BTree::from_sorted(left, md);
+        self.dup_policy = policy;
+        let mut right = BTree::from_sorted(right, md);
+        right.dup_policy = policy;
+        right
+    }
+
+    ///Moves every entry of other into this tree, with other's values
+    ///winning when both trees hold a key.  See merge_with for the cost.
+    pub fn append(&mut self, other: BTree<K, V>) {
+        self.merge_with(other, |v, w| *v = w);
+    }
+
+    ///Moves every entry of other into this tree.  When both trees hold a
+    ///key, f is given this tree's value to update and other's value to
+    ///fold into it.  If the trees have the same minimum degree and their
+    ///keys don't interleave, one is spliced into the other in time
+    ///proportional to their heights.  If other is small next to this
+    ///tree, its entries are inserted one at a time.  Otherwise the trees
+    ///are merged in order and rebuilt, in time linear in their total size.
+    pub fn merge_with(&mut self, other: BTree<K, V>, f: |&mut V, V|) {
+        if other.is_empty() {
+            return;
+        }
+        let md = self.min_deg;
+        let policy = self.dup_policy;
+        if other.min_deg == md {
+            if self.is_below(&other) {
+                self.splice_after(other);
+                return;
+            }
+            if other.is_below(self) {
+                let upper = replace(self, other);
+                self.dup_policy = policy;
+                self.splice_after(upper);
+                return;
+            }
+        }
+        //Each insert is a descent, so inserting beats rebuilding while
+        //the descents cost less than visiting every entry.
+        if other.len * self.height < self.len {
+            for (k, w) in other.move_iter() {
+                match self.entry(k) {
+                    Occupied(mut entry) => f(entry.get_mut(), w),
+                    Vacant(entry) => {
+                        entry.insert(w);
+                    }
                 }
-                Greater => {
-                    min = mid;
+            }
+            return;
+        }
+        let tree = replace(self, BTree::with_degree(md));
+        let mut pairs = vec::with_capacity(tree.len + other.len);
+        let mut left = tree.move_iter().peekable();
+        let mut right = other.move_iter().peekable();
+        loop {
+            let order = match (left.peek(), right.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Less,
+                (None, Some(_)) => Greater,
+                (Some(&(ref k1, _)), Some(&(ref k2, _))) => k1.cmp(k2)
+            };
+            match order {
+                Less => pairs.push(left.next().unwrap()),
+                Greater => pairs.push(right.next().unwrap()),
+                Equal => {
+                    let (k, mut v) = left.next().unwrap();
+                    let (_, w) = right.next().unwrap();
+                    f(&mut v, w);
+                    pairs.push((k, v));
                 }
             }
-            mid = (min + max) / 2;
         }
-        mid
+        *self = BTree::from_sorted(pairs, md);
+        self.dup_policy = policy;
     }
-}
 
-#[cfg(test)]
-mod test_btree {
-    use super::{BTree, Node, Elt};
+    //Returns true if every key in this tree is less than every key in
+    //other, as it is when either tree is empty.
+    fn is_below(&self, other: &BTree<K, V>) -> bool {
+        match (self.max(), other.min()) {
+            (Some((last, _)), Some((first, _))) => last.cmp(first) == Less,
+            _ => true
+        }
+    }
 
-    #[test]
-    fn split_child_test_1() {
-        let mut new_node = Node { elts: ~[Elt { key: 3, value: ~"a" },
-                                          Elt { key: 7, value: ~"b" }],
-                                  children: Some(~[~Node { elts: ~[Elt { key: 1, value: ~"c" },
-                                                                   Elt { key: 2, value: ~"d" }],
-                                                           children: None },
-                                                   ~Node { elts: ~[Elt { key: 4, value: ~"e" },
-                                                                   Elt { key: 5, value: ~"f" },
-                                                                   Elt { key: 6, value: ~"g" }],
-                                                           children: None },
-                                                   ~Node { elts: ~[Elt { key: 8, value: ~"h" },
-                                                                   Elt { key: 9, value: ~"i" }],
-                                                           children: None }])};
-        new_node.split_child(1, 2);
-        assert_eq!(new_node.elts[1].key, 5);
+    //Joins other, which has the same minimum degree and only keys greater
+    //than this tree's, onto this tree.  The shorter tree's smallest or
+    //largest entry is taken out to separate the two, and the rest of it
+    //hangs from the edge of the taller tree at the level where the
+    //heights match.
+    fn splice_after(&mut self, other: BTree<K, V>) {
+        let policy = self.dup_policy;
+        if self.is_empty() {
+            *self = other;
+            self.dup_policy = policy;
+            return;
+        }
+        let mut other = other;
+        if self.height >= other.height {
+            let (k, v) = other.pop_min().unwrap();
+            self.graft(Elt { key: k, value: v }, other, true);
+        } else {
+            let (k, v) = self.pop_max().unwrap();
+            let lower = replace(self, other);
+            self.dup_policy = policy;
+            self.graft(Elt { key: k, value: v }, lower, false);
+        }
     }
 
-    #[test]
-    fn split_child_test_2() {
-        let mut new_node = Node { elts: ~[Elt { key: 3, value: ~"a" },
-                                          Elt { key: 7, value: ~"b" }],
-                                  children: Some(~[~Node { elts: ~[Elt { key: 1, value: ~"c" },
-                                                                   Elt { key: 2, value: ~"d" }],
-                                                           children: None },
-                                                   ~Node { elts: ~[Elt { key: 4, value: ~"e" },
-                                                                   Elt { key: 5, value: ~"f" },
-                                                                   Elt { key: 6, value: ~"g" }],
-                                                           children: None },
-                                                   ~Node { elts: ~[Elt { key: 8, value: ~"h" },
-                                                                   Elt { key: 9, value: ~"i" }],
-                                                           children: None }])};
-        new_node.split_child(1, 2);
-        assert_eq!(new_node.children.unwrap()[1].elts[0].key, 4);
+    //Hangs other, which is no taller than this tree, on this tree's right
+    //edge if on_right is true and on its left edge if not, with sep between
+    //the two.  sep must sort between this tree's keys and other's.
+    fn graft(&mut self, sep: Elt<K, V>, other: BTree<K, V>, on_right: bool) {
+        if other.is_empty() {
+            self.insert(sep.key, sep.value);
+            return;
+        }
+        let BTree { root: other_root, len: other_len, height: other_height, .. } = other;
+        let added = other_len + 1;
+        let min_elts = self.min_elts();
+        if other_height == self.height {
+            let old_root = replace(&mut self.root, Node { elts: vec!(sep), kind: Leaf });
+            let kids = if on_right {
+                vec!(~old_root, ~other_root)
+            } else {
+                vec!(~other_root, ~old_root)
+            };
+            self.root.kind = Branch(kids, 0);
+            self.root.recount_size();
+            self.root.balance_kids(0, min_elts);
+            self.height += 1;
+        } else {
+            self.split_full_root();
+            let ub = self.max_elts();
+            let depth = self.height - other_height - 1;
+            let mut node = &mut self.root;
+            //Full nodes on the way down are split, as for insert, so the
+            //node that takes sep has room for it.
+            for _ in range(0, depth) {
+                let size = node.size();
+                node.set_size(size + added);
+                let last = node.kids().len() - 1;
+                let i = if on_right { last } else { 0 };
+                node.split_child(i, ub);
+                let last = node.kids().len() - 1;
+                let i = if on_right { last } else { 0 };
+                let tmp = node;
+                node = &mut **tmp.mut_kids().get_mut(i);
+            }
+            let size = node.size();
+            node.set_size(size + added);
+            if on_right {
+                node.elts.push(sep);
+                node.mut_kids().push(~other_root);
+                let last = node.kids().len() - 1;
+                node.balance_kids(last - 1, min_elts);
+            } else {
+                node.elts.insert(0, sep);
+                node.mut_kids().insert(0, ~other_root);
+                node.balance_kids(0, min_elts);
+            }
+        }
+        self.len += added;
+        self.shrink_empty_root();
     }
 
-    #[test]
-    fn split_child_test_3() {
-        let mut new_node = Node { elts: ~[Elt { key: 3, value: ~"a" },
-                                          Elt { key: 11, value: ~"b" }],
-                                  children: Some(~[~Node { elts: ~[Elt { key: 1, value: ~"c" },
-                                                                   Elt { key: 2, value: ~"d" }],
-                                                           children: None },
-                                                   ~Node { elts: ~[Elt { key: 5, value: ~"e" },
-                                                                   Elt { key: 7, value: ~"f" },
-                                                                   Elt { key: 9, value: ~"g" }],
-                                                           children: Some(~[~Node { elts: ~[Elt { key: 4, value: ~"h" }],
-                                                                                    children: None },
-                                                                            ~Node { elts: ~[Elt { key: 6, value: ~"i" }],
-                                                                                    children: None },
-                                                                            ~Node { elts: ~[Elt { key: 8, value: ~"j" }],
-                                                                                    children: None },
-                                                                            ~Node { elts: ~[Elt { key: 10, value: ~"k" }],
-                                                                                    children: None }])},
-                                                   ~Node { elts: ~[Elt { key: 12, value: ~"l" },
-                                                                   Elt { key: 13, value: ~"m" }],
-                                                           children: None }])};
-        new_node.split_child(1, 2);
-        assert_eq!(new_node.children.unwrap()[1].children.unwrap()[0].elts[0].key, 4);
+    ///Keeps only the entries for which f returns true.  The survivors are
+    ///still in order, so the tree is rebuilt from them rather than having
+    ///the others removed one at a time.
+    pub fn retain(&mut self, f: |&K, &mut V| -> bool) {
+        let md = self.min_deg;
+        let policy = self.dup_policy;
+        let tree = replace(self, BTree::with_degree(md));
+        let mut kept = vec::with_capacity(tree.len);
+        for (k, v) in tree.move_iter() {
+            let mut v = v;
+            if f(&k, &mut v) {
+                kept.push((k, v));
+            }
+        }
+        *self = BTree::from_sorted(kept, md);
+        self.dup_policy = policy;
     }
 
-    #[test]
-    fn insert_test_1() {
-        let mut new_tree = BTree { root: Node { elts: ~[Elt { key: 1, value: ~"a" },
-                                                        Elt { key: 3, value: ~"c" }],
-                                                children: None },
-                                   min_deg: 2 };
-        new_tree.insert(2, ~"b");
-        assert_eq!(new_tree.root.elts[1].key, 2);
+    ///Removes every entry whose key lies in [lo, hi), returning how many
+    ///there were.  The count comes from ranks; if it is not zero, the
+    ///survivors are rebuilt into a tree once, as in retain, rather than
+    ///rebalancing after each removal.
+    pub fn remove_range(&mut self, lo: &K, hi: &K) -> uint {
+        let count = self.count_range(lo, hi);
+        if count > 0 {
+            self.retain(|k, _| k.cmp(lo) == Less || k.cmp(hi) != Less);
+        }
+        count
     }
 
-    #[test]
-    fn insert_test_2() {
-        let mut new_tree = BTree { root: Node { elts: ~[Elt { key: 1, value: ~"a" },
-                                                        Elt { key: 2, value: ~"b" }],
-                                                children: None },
-                                   min_deg: 2 };
-        new_tree.insert(3, ~"c");
-        assert_eq!(new_tree.root.elts[2].key, 3);
+    ///Removes all but the n smallest entries.  Like retain, this rebuilds
+    ///the tree from the entries it keeps; a tree with no more than n
+    ///entries is left alone.
+    pub fn truncate(&mut self, n: uint) {
+        if n >= self.len {
+            return;
+        }
+        let mut seen = 0;
+        self.retain(|_, _| {
+            seen += 1;
+            seen <= n
+        });
     }
 
-    #[test]
-    fn insert_test_3() {
-        let mut new_tree = BTree { root: Node { elts: ~[Elt { key: 2, value: ~"b" },
-                                                        Elt { key: 3, value: ~"c" }],
-                                                children: None },
-                                   min_deg: 2 };
-        new_tree.insert(1, ~"a");
-        assert_eq!(new_tree.root.elts[0].key, 1);
+    ///Returns the entry with the smallest key, found by following the
+    ///leftmost children down to a leaf.
+    pub fn min<'a>(&'a self) -> Option<(&'a K, &'a V)> {
+        let mut node = &self.root;
+        loop {
+            match node.kind {
+                Leaf => return node.elts.as_slice().head().map(|elt| (&elt.key, &elt.value)),
+                Branch(ref kids, _) => node = &**kids.get(0)
+            }
+        }
     }
 
-    #[test]
-    fn insert_test_4() {
-        let mut new_tree = BTree { root: Node { elts: ~[Elt { key: 1, value: ~"a" },
-                                                        Elt { key: 2, value: ~"b" },
-                                                        Elt { key: 3, value: ~"c" },
-                                                        Elt { key: 4, value: ~"d" }],
-                                                children: None },
-                                   min_deg: 2 };
-        new_tree.insert(5, ~"3");
-        assert_eq!(new_tree.root.elts[0].key, 2);
+    ///Returns the entry with the largest key, found by following the
+    ///rightmost children down to a leaf.
+    pub fn max<'a>(&'a self) -> Option<(&'a K, &'a V)> {
+        let mut node = &self.root;
+        loop {
+            match node.kind {
+                Leaf => return node.elts.last().map(|elt| (&elt.key, &elt.value)),
+                Branch(ref kids, _) => node = &**kids.get(kids.len() - 1)
+            }
+        }
     }
 
-    #[test]
-    fn insert_test_5() {
-        let mut new_node = Node { elts: ~[Elt { key: 2, value: ~"a" },
-                                          Elt { key: 8, value: ~"b" }],
-                                  children: Some(~[~Node { elts: ~[Elt { key: 0, value: ~"c" },
-                                                                   Elt { key: 1, value: ~"d" }],
-                                                           children: None },
-                                                   ~Node { elts: ~[Elt { key: 3, value: ~"x" },
-                                                                   Elt { key: 4, value: ~"e" },
-                                                                   Elt { key: 6, value: ~"f" },
-                                                                   Elt { key: 7, value: ~"g" }],
-                                                           children: None },
-                                                   ~Node { elts: ~[Elt { key: 9, value: ~"h" },
-                                                                   Elt { key: 10, value: ~"i" }],
-                                                           children: None }])};
-        let mut new_tree = BTree { root: new_node,
-                                   min_deg: 2 };
-        new_tree.insert(5, ~"omg");
-        assert_eq!(new_tree.root.elts[1].key, 4);
+    ///Returns the first entry that iter() would yield; the same as min().
+    pub fn first<'a>(&'a self) -> Option<(&'a K, &'a V)> {
+        self.min()
+    }
+
+    ///Returns the last entry that iter() would yield; the same as max().
+    pub fn last<'a>(&'a self) -> Option<(&'a K, &'a V)> {
+        self.max()
+    }
+
+    ///Returns the value stored under the smallest key, or None if the tree
+    ///is empty.
+    pub fn peek_min<'a>(&'a self) -> Option<&'a V> {
+        self.min().map(|(_, v)| v)
+    }
+
+    ///Returns the value stored under the largest key, or None if the tree
+    ///is empty.
+    pub fn peek_max<'a>(&'a self) -> Option<&'a V> {
+        self.max().map(|(_, v)| v)
+    }
+
+    ///Returns the entry with the greatest key that is less than or equal
+    ///to k, or None if every key is greater than k.
+    pub fn floor<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)> {
+        self.root.floor(k).map(|elt| (&elt.key, &elt.value))
+    }
+
+    ///Returns the entry with the smallest key that is greater than or equal
+    ///to k, or None if every key is less than k.
+    pub fn ceiling<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)> {
+        self.root.lower_bound(k).map(|elt| (&elt.key, &elt.value))
+    }
+
+    ///Returns the entry with the greatest key that is strictly less than k,
+    ///whether or not k is in the tree.
+    pub fn predecessor<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)> {
+        self.root.predecessor(k).map(|elt| (&elt.key, &elt.value))
+    }
+
+    ///Returns the entry with the smallest key that is strictly greater
+    ///than k, whether or not k is in the tree.
+    pub fn successor<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)> {
+        self.root.successor(k).map(|elt| (&elt.key, &elt.value))
+    }
+
+    ///Looks up k and the entry just after it in a single descent.  Returns
+    ///the value stored under k, if any, and the entry with the smallest key
+    ///greater than k, which is where k sits in the order of keys.
+    pub fn equal_range<'a>(&'a self, k: &K) -> (Option<&'a V>, Option<(&'a K, &'a V)>) {
+        let mut node = &self.root;
+        let mut found = None;
+        let mut next = None;
+        loop {
+            let mut index = node.bsearch_node(k);
+            if index < node.elts.len() && node.elts.get(index).key.cmp(k) == Equal {
+                found = Some(&node.elts.get(index).value);
+                index += 1;
+            }
+            if index < node.elts.len() {
+                next = Some(node.elts.get(index));
+            }
+            match node.kind {
+                Leaf => return (found, next.map(|elt| (&elt.key, &elt.value))),
+                Branch(ref kids, _) => node = &**kids.get(index)
+            }
+        }
+    }
+
+    ///Returns the entry with the n-th smallest key, counting from zero, or
+    ///None if the tree has n or fewer entries.  Takes time proportional to
+    ///the height of the tree, using the subtree sizes cached in branches.
+    pub fn select<'a>(&'a self, n: uint) -> Option<(&'a K, &'a V)> {
+        if n >= self.len {
+            return None;
+        }
+        let mut node = &self.root;
+        let mut n = n;
+        loop {
+            match node.kind {
+                Leaf => {
+                    let elt = node.elts.get(n);
+                    return Some((&elt.key, &elt.value));
+                }
+                Branch(ref kids, _) => {
+                    //Skip whole children, and the separators after them,
+                    //until the n-th entry is in a child or is a separator.
+                    let mut i = 0;
+                    loop {
+                        let size = kids.get(i).size();
+                        if n < size {
+                            break;
+                        }
+                        if n == size {
+                            let elt = node.elts.get(i);
+                            return Some((&elt.key, &elt.value));
+                        }
+                        n -= size + 1;
+                        i += 1;
+                    }
+                    node = &**kids.get(i);
+                }
+            }
+        }
+    }
+
+    ///Returns the n-th smallest entry, counting from zero.  An alias of
+    ///select.
+    pub fn nth<'a>(&'a self, n: uint) -> Option<(&'a K, &'a V)> {
+        self.select(n)
     }
 
+    ///Returns the number of keys in the tree that are less than k.
+    pub fn rank(&self, k: &K) -> uint {
+        let mut node = &self.root;
+        let mut rank = 0;
+        loop {
+            let index = node.bsearch_node(k);
+            rank += index;
+            match node.kind {
+                Leaf => return rank,
+                Branch(ref kids, _) => {
+                    for kid in kids.iter().take(index) {
+                        rank += kid.size();
+                    }
+                    //If k is the separator at index, only its left child
+                    //is left to count.
+                    if index < node.elts.len() && node.elts.get(index).key.cmp(k) == Equal {
+                        return rank + kids.get(index).size();
+                    }
+                    node = &**kids.get(index);
+                }
+            }
+        }
+    }
 
+    ///Returns the position of k in ascending key order, counting from
+    ///zero, or None if k is not in the tree.  Like rank, this walks a
+    ///single path from the root.
+    pub fn position_of(&self, k: &K) -> Option<uint> {
+        let mut node = &self.root;
+        let mut rank = 0;
+        loop {
+            let index = node.bsearch_node(k);
+            rank += index;
+            let found = index < node.elts.len() && node.elts.get(index).key.cmp(k) == Equal;
+            match node.kind {
+                Leaf => return if found { Some(rank) } else { None },
+                Branch(ref kids, _) => {
+                    for kid in kids.iter().take(index) {
+                        rank += kid.size();
+                    }
+                    if found {
+                        return Some(rank + kids.get(index).size());
+                    }
+                    node = &**kids.get(index);
+                }
+            }
+        }
+    }
+
+    ///Returns the number of keys in [lo, hi), which is zero unless lo is
+    ///less than hi.  No entries are visited; the count is the difference
+    ///of two ranks.
+    pub fn count_range(&self, lo: &K, hi: &K) -> uint {
+        if lo.cmp(hi) != Less {
+            return 0;
+        }
+        self.rank(hi) - self.rank(lo)
+    }
+
+    ///Returns the number of keys for which pred returns true.  Every key is
+    ///visited; when the matching keys form a single range, count_range
+    ///gives the same answer without visiting any.
+    pub fn count_matching(&self, pred: |&K| -> bool) -> uint {
+        self.keys().count(|k| pred(k))
+    }
+
+    ///Returns the number of levels in the tree; a tree whose root is a
+    ///leaf has height 1.  The height is kept up to date as the root splits
+    ///and collapses, so this takes constant time.
+    pub fn height(&self) -> uint {
+        self.height
+    }
+
+    ///Returns the minimum degree the tree was created with.
+    pub fn min_deg(&self) -> uint {
+        self.min_deg
+    }
+
+    ///Makes room in the root for additional more elements, so that a run
+    ///of inserts does not reallocate it.  No node holds more than
+    ///2 * min_deg - 1 elements, so no more room than that is reserved.
+    pub fn reserve(&mut self, additional: uint) {
+        let ub = self.max_elts();
+        let wanted = min(self.root.elts.len() + additional, ub);
+        if wanted > self.root.elts.capacity() {
+            self.root.elts.reserve_exact(wanted);
+        }
+    }
+
+    ///Releases the spare capacity of every node's element and child
+    ///vectors, which can be worth doing after many removals.
+    pub fn shrink_to_fit(&mut self) {
+        self.root.shrink_to_fit();
+    }
+
+    ///Returns the number of elements the tree's nodes have room for
+    ///without reallocating, summed over every node.  This counts what has
+    ///been allocated, so it is never less than len().
+    pub fn capacity(&self) -> uint {
+        self.root.elt_capacity()
+    }
+
+    ///Returns the total number of nodes in the tree.
+    pub fn node_count(&self) -> uint {
+        self.root.node_count()
+    }
+
+    ///Returns an iterator over the keys of each node, visiting the nodes
+    ///level by level from the root down and left to right within a level.
+    ///Meant for looking at the shape of the tree while debugging.
+    pub fn levels<'a>(&'a self) -> LevelOrder<'a, K, V> {
+        let mut queue = RingBuf::new();
+        queue.push_back(&self.root);
+        LevelOrder { queue: queue }
+    }
+
+    ///Returns the keys of each node that a lookup of k visits, from the
+    ///root down to the node holding k, or to the leaf where the search
+    ///ends if k is not in the tree.  Meant for seeing why a lookup took
+    ///the route it did.
+    pub fn get_path<'a>(&'a self, k: &K) -> ~[~[&'a K]] {
+        let mut path = ~[];
+        let mut node = &self.root;
+        loop {
+            path.push(node.elts.iter().map(|elt| &elt.key).collect());
+            let index = node.bsearch_node(k);
+            if index < node.elts.len() && node.elts.get(index).key.cmp(k) == Equal {
+                return path;
+            }
+            match node.kind {
+                Leaf => return path,
+                Branch(ref kids, _) => node = &**kids.get(index)
+            }
+        }
+    }
+
+    ///Returns the number of elements stored at each level of the tree,
+    ///from the root down to the leaves.  Comparing these with the number
+    ///of nodes at each level shows how well the minimum degree suits the
+    ///workload.
+    pub fn level_stats(&self) -> ~[uint] {
+        let mut stats = vec::with_capacity(self.height);
+        let mut level = ~[&self.root];
+        while !level.is_empty() {
+            stats.push(level.iter().fold(0, |n, node| n + node.elts.len()));
+            let mut next = ~[];
+            for node in level.iter() {
+                match node.kind {
+                    Leaf => {}
+                    Branch(ref kids, _) => {
+                        for kid in kids.iter() {
+                            next.push(&**kid);
+                        }
+                    }
+                }
+            }
+            level = next;
+        }
+        stats
+    }
+
+    ///Returns the fraction of the tree's capacity in use: the number of
+    ///elements divided by what its nodes could hold if they were all full.
+    ///Bulk loading packs nodes more tightly than inserting one key at a
+    ///time, and this shows by how much.
+    pub fn fill_ratio(&self) -> f64 {
+        self.len as f64 / (self.node_count() * self.max_elts()) as f64
+    }
+
+    ///Checks the B-tree invariants: every node below the root holds
+    ///between min_deg - 1 and 2 * min_deg - 1 elements, keys within each
+    ///node are strictly ascending and lie between the separators around
+    ///it, branches have one more child than they have elements and know
+    ///the size of their subtree, and all leaves are at the same depth,
+    ///which is the height of the tree.
+    pub fn validate(&self) -> bool {
+        self.root.size() == self.len &&
+            self.root.validate(self.min_deg, true, None, None) == Some(self.height)
+    }
+
+    ///Returns an iterator over the key-value pairs of the tree, in
+    ///ascending key order.
+    pub fn iter<'a>(&'a self) -> Entries<'a, K, V> {
+        let mut iter = Entries { front: Vec::new(), back: Vec::new(), remaining: self.len };
+        iter.push_left(&self.root);
+        iter.push_right(&self.root);
+        iter
+    }
+
+    ///Returns an iterator over the key-value pairs of the tree, in
+    ///ascending key order, through which the values can be changed.
+    pub fn mut_iter<'a>(&'a mut self) -> MutEntries<'a, K, V> {
+        let mut iter = MutEntries { stack: Vec::new(), remaining: self.len,
+                                    marker: marker::ContravariantLifetime::<'a> };
+        iter.push_left(&mut self.root as *mut Node<K, V>);
+        iter
+    }
+
+    ///Returns an iterator over the key-value pairs whose keys are not less
+    ///than start, in ascending key order.  The iterator seeks straight to
+    ///start rather than skipping the entries before it.
+    pub fn iter_from<'a>(&'a self, start: &K) -> Entries<'a, K, V> {
+        self.range_bounds(Included(start), Unbounded)
+    }
+
+    ///Returns an iterator over the key-value pairs whose keys lie in
+    ///[lo, hi), in ascending key order, through which the values can be
+    ///changed.  As with range, subtrees outside the range are never
+    ///visited.
+    pub fn range_mut<'a>(&'a mut self, lo: &K, hi: &K) -> MutEntries<'a, K, V> {
+        let mut iter = MutEntries { stack: Vec::new(), remaining: 0,
+                                    marker: marker::ContravariantLifetime::<'a> };
+        if self.root.elts.is_empty() || lo.cmp(hi) != Less {
+            return iter;
+        }
+        iter.remaining = self.rank(hi) - self.rank(lo);
+        iter.seek(&mut self.root as *mut Node<K, V>, lo);
+        iter
+    }
+
+    ///Returns an iterator over the key-value pairs of the tree, in
+    ///descending key order.
+    pub fn rev_iter<'a>(&'a self) -> Rev<Entries<'a, K, V>> {
+        self.iter().rev()
+    }
+
+    ///Returns an iterator over the key-value pairs whose keys lie in
+    ///[lo, hi), in ascending key order.  Subtrees entirely outside the
+    ///range are never visited.
+    pub fn range<'a>(&'a self, lo: &K, hi: &K) -> Entries<'a, K, V> {
+        self.range_bounds(Included(lo), Excluded(hi))
+    }
+
+    ///Returns the values whose keys lie in [lo, hi), in ascending key
+    ///order.  The vector is empty unless lo is less than hi.
+    pub fn range_values<'a>(&'a self, lo: &K, hi: &K) -> ~[&'a V] {
+        self.range(lo, hi).map(|(_, v)| v).collect()
+    }
+
+    ///Folds f over the values whose keys lie in [lo, hi), in ascending
+    ///key order, starting from init.  The range is walked as by range, so
+    ///subtrees outside it are skipped and nothing is collected.
+    pub fn fold_range<A>(&self, lo: &K, hi: &K, init: A, f: |A, &V| -> A) -> A {
+        let mut acc = init;
+        for (_, v) in self.range(lo, hi) {
+            acc = f(acc, v);
+        }
+        acc
+    }
+
+    ///Returns an iterator over the key-value pairs whose keys lie between
+    ///lo and hi, in ascending key order.  Each endpoint may be included,
+    ///excluded or left unbounded; an empty range yields nothing.
+    pub fn range_bounds<'a>(&'a self, lo: Bound<&K>, hi: Bound<&K>) -> Entries<'a, K, V> {
+        let mut iter = Entries { front: Vec::new(), back: Vec::new(), remaining: 0 };
+        let start = match lo {
+            Unbounded => 0,
+            Included(k) => self.rank(k),
+            Excluded(k) => self.rank(k) + if self.contains_key(k) { 1 } else { 0 }
+        };
+        let end = match hi {
+            Unbounded => self.len,
+            Included(k) => self.rank(k) + if self.contains_key(k) { 1 } else { 0 },
+            Excluded(k) => self.rank(k)
+        };
+        if start >= end {
+            return iter;
+        }
+        iter.remaining = end - start;
+        match lo {
+            Unbounded => iter.push_left(&self.root),
+            Included(k) => iter.seek(&self.root, k, true),
+            Excluded(k) => iter.seek(&self.root, k, false)
+        }
+        match hi {
+            Unbounded => iter.push_right(&self.root),
+            Included(k) => iter.seek_back(&self.root, k, true),
+            Excluded(k) => iter.seek_back(&self.root, k, false)
+        }
+        iter
+    }
+
+    ///Returns an iterator over the keys of the tree, in ascending order.
+    pub fn keys<'a>(&'a self) -> Keys<'a, K, V> {
+        Keys { iter: self.iter() }
+    }
+
+    ///Returns an iterator over the values of the tree, in ascending
+    ///order of their keys.
+    pub fn values<'a>(&'a self) -> Values<'a, K, V> {
+        Values { iter: self.iter() }
+    }
+
+    ///Returns an iterator over the values of the tree, in ascending order
+    ///of their keys, through which the values can be changed.
+    pub fn mut_values<'a>(&'a mut self) -> MutValues<'a, K, V> {
+        MutValues { iter: self.mut_iter() }
+    }
+
+    ///Consumes the tree, returning an iterator over its key-value pairs
+    ///in ascending key order.
+    pub fn move_iter(self) -> MoveEntries<K, V> {
+        let BTree { root: root, .. } = self;
+        let mut iter = MoveEntries { stack: Vec::new() };
+        iter.push_left(root);
+        iter
+    }
+
+    ///Consumes the tree, returning an iterator over its keys in ascending
+    ///order.
+    pub fn into_keys(self) -> MoveKeys<K, V> {
+        MoveKeys { iter: self.move_iter() }
+    }
+
+    ///Consumes the tree, returning an iterator over its values in
+    ///ascending order of their keys.
+    pub fn into_values(self) -> MoveValues<K, V> {
+        MoveValues { iter: self.move_iter() }
+    }
+
+    ///Empties the tree, returning an iterator that moves its key-value
+    ///pairs out in ascending key order.  The tree is left empty, with the
+    ///same minimum degree, however much of the iterator is used.
+    pub fn drain(&mut self) -> MoveEntries<K, V> {
+        let md = self.min_deg;
+        let policy = self.dup_policy;
+        replace(self, BTree::with_dup_policy(md, policy)).move_iter()
+    }
+}
+
+impl<K: TotalOrd + Clone, V> BTree<K, V> {
+    ///Returns a tree with the same keys, and the same shape, whose values
+    ///are those of this tree passed through f.
+    pub fn map_values<W>(&self, f: |&V| -> W) -> BTree<K, W> {
+        BTree {
+            root: self.root.map_values(f),
+            len: self.len,
+            height: self.height,
+            min_deg: self.min_deg,
+            dup_policy: self.dup_policy
+        }
+    }
+}
+
+impl<K: TotalOrd + Clone, V: Clone> BTree<K, V> {
+    ///Returns a copy of the entries of the tree, in ascending key order.
+    pub fn to_vec(&self) -> ~[(K, V)] {
+        let mut pairs = vec::with_capacity(self.len);
+        for (k, v) in self.iter() {
+            pairs.push((k.clone(), v.clone()));
+        }
+        pairs
+    }
+}
+
+impl<K: TotalOrd, V: Eq> BTree<K, V> {
+    ///Returns true if some entry has the value v.  The entries are scanned
+    ///in key order, stopping at the first match.
+    pub fn contains_value(&self, v: &V) -> bool {
+        self.values().any(|value| value == v)
+    }
+}
+
+impl<K: TotalOrd + Sub<K, K>, V> BTree<K, V> {
+    ///Returns whichever of floor(k) and ceiling(k) has the key closest to
+    ///k, taking the smaller key when they are equally close, or None if
+    ///the tree is empty.
+    pub fn find_nearest<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)> {
+        match (self.floor(k), self.ceiling(k)) {
+            (None, None) => None,
+            (Some(below), None) => Some(below),
+            (None, Some(above)) => Some(above),
+            (Some(below), Some(above)) => {
+                let (lo, _) = below;
+                let (hi, _) = above;
+                if (*hi - *k).cmp(&(*k - *lo)) == Less { Some(above) } else { Some(below) }
+            }
+        }
+    }
+}
+
+impl<K: TotalOrd + fmt::Show, V> BTree<K, V> {
+    ///Returns a Graphviz digraph with a record for each node, listing its
+    ///keys, and an edge from each branch to each of its children.  Nodes
+    ///are numbered in level order, so a tree of a given shape always gives
+    ///the same output.
+    pub fn to_dot(&self) -> ~str {
+        let mut dot = ~"digraph btree {\n    node [shape=record];\n";
+        let mut queue = RingBuf::new();
+        queue.push_back((&self.root, 0u));
+        let mut next_id = 1u;
+        loop {
+            let (node, id) = match queue.pop_front() {
+                None => break,
+                Some(entry) => entry
+            };
+            let keys: ~[~str] = node.elts.iter()
+                .map(|elt| escape_record(elt.key.to_str()))
+                .collect();
+            dot.push_str(format!("    n{} [label=\"{}\"];\n", id, keys.connect("|")));
+            match node.kind {
+                Leaf => {}
+                Branch(ref kids, _) => {
+                    for kid in kids.iter() {
+                        dot.push_str(format!("    n{} -> n{};\n", id, next_id));
+                        queue.push_back((&**kid, next_id));
+                        next_id += 1;
+                    }
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+//Backslash-escapes the characters that have a meaning inside a Graphviz
+//record label.
+fn escape_record(s: &str) -> ~str {
+    let mut out = ~"";
+    for c in s.chars() {
+        match c {
+            '{' | '}' | '|' | '<' | '>' | '"' | '\\' => out.push_char('\\'),
+            _ => {}
+        }
+        out.push_char(c);
+    }
+    out
+}
+
+impl<K: Clone, V: Clone> Clone for BTree<K, V> {
+    fn clone(&self) -> BTree<K, V> {
+        BTree { root: self.root.clone(), len: self.len, height: self.height,
+                min_deg: self.min_deg, dup_policy: self.dup_policy }
+    }
+
+    ///Copies source into this tree, reusing this tree's nodes where they
+    ///line up with source's rather than allocating them afresh.
+    fn clone_from(&mut self, source: &BTree<K, V>) {
+        self.root.clone_from(&source.root);
+        self.len = source.len;
+        self.height = source.height;
+        self.min_deg = source.min_deg;
+        self.dup_policy = source.dup_policy;
+    }
+}
+
+impl<K: TotalOrd, V: Eq> Eq for BTree<K, V> {
+    ///Two trees are equal if they hold the same key-value pairs, whatever
+    ///the shape of their nodes.
+    fn eq(&self, other: &BTree<K, V>) -> bool {
+        self.len() == other.len() &&
+            self.iter().zip(other.iter()).all(|((k1, v1), (k2, v2))| {
+                k1.cmp(k2) == Equal && *v1 == *v2
+            })
+    }
+}
+
+impl<K: TotalOrd, V: TotalEq> TotalEq for BTree<K, V> {
+    fn equals(&self, other: &BTree<K, V>) -> bool {
+        self.len() == other.len() &&
+            self.iter().zip(other.iter()).all(|((k1, v1), (k2, v2))| {
+                k1.cmp(k2) == Equal && v1.equals(v2)
+            })
+    }
+}
+
+impl<K: TotalOrd, V: TotalOrd> TotalOrd for BTree<K, V> {
+    ///Compares the entries of the two trees in ascending key order,
+    ///lexicographically; a tree that is a prefix of the other is smaller.
+    fn cmp(&self, other: &BTree<K, V>) -> Ordering {
+        for ((k1, v1), (k2, v2)) in self.iter().zip(other.iter()) {
+            match k1.cmp(k2) {
+                Equal => {}
+                order => return order
+            }
+            match v1.cmp(v2) {
+                Equal => {}
+                order => return order
+            }
+        }
+        self.len().cmp(&other.len())
+    }
+}
+
+impl<K: TotalOrd, V: TotalOrd> Ord for BTree<K, V> {
+    fn lt(&self, other: &BTree<K, V>) -> bool {
+        self.cmp(other) == Less
+    }
+}
+
+impl<S: Writer, K: TotalOrd + Hash<S>, V: Hash<S>> Hash<S> for BTree<K, V> {
+    ///Hashes the length and then each entry in ascending key order, so
+    ///trees that are equal hash the same whatever the shape of their nodes.
+    fn hash(&self, state: &mut S) {
+        self.len.hash(state);
+        for (k, v) in self.iter() {
+            k.hash(state);
+            v.hash(state);
+        }
+    }
+}
+
+impl<K: TotalOrd, V> Container for BTree<K, V> {
+    ///Returns the number of key-value pairs in the tree.
+    fn len(&self) -> uint {
+        self.len
+    }
+
+    ///Returns true if the tree holds no key-value pairs.
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<K: TotalOrd, V> Mutable for BTree<K, V> {
+    ///Removes every key-value pair, leaving an empty leaf as the root.
+    fn clear(&mut self) {
+        self.root = Node { elts: Vec::new(), kind: Leaf };
+        self.len = 0;
+        self.height = 1;
+    }
+}
+
+impl<K: TotalOrd, V> Default for BTree<K, V> {
+    ///Returns an empty tree with a minimum degree of 6, so that a full node
+    ///holds 11 elements: enough to keep the tree shallow, while a search
+    ///within a node still touches only a few cache lines.
+    fn default() -> BTree<K, V> {
+        BTree::with_degree(DEFAULT_MIN_DEG)
+    }
+}
+
+impl<K: TotalOrd, V> Map<K, V> for BTree<K, V> {
+    fn find<'a>(&'a self, k: &K) -> Option<&'a V> {
+        self.find(k)
+    }
+}
+
+impl<K: TotalOrd, V> MutableMap<K, V> for BTree<K, V> {
+    fn find_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+        self.find_mut(k)
+    }
+
+    fn swap(&mut self, k: K, v: V) -> Option<V> {
+        self.swap(k, v)
+    }
+
+    fn pop(&mut self, k: &K) -> Option<V> {
+        self.remove(k)
+    }
+}
+
+impl<K: TotalOrd, V: Clone> Index<K, V> for BTree<K, V> {
+    ///Returns a copy of the value stored under the given key.  Index hands
+    ///back its result by value, hence the Clone bound.  Fails if the key is
+    ///not in the tree.
+    fn index(&self, k: &K) -> V {
+        match self.find(k) {
+            Some(v) => v.clone(),
+            None => fail!("BTree::index: key not found")
+        }
+    }
+}
+
+impl<K: TotalOrd + fmt::Show, V: fmt::Show> fmt::Show for BTree<K, V> {
+    ///Formats the tree as {k1: v1, k2: v2, ...} in ascending key order.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f.buf, r"\{"));
+        for (i, (k, v)) in self.iter().enumerate() {
+            if i > 0 { try!(write!(f.buf, ", ")) }
+            try!(write!(f.buf, "{}: {}", *k, *v));
+        }
+        write!(f.buf, r"\}")
+    }
+}
+
+impl<K: TotalOrd, V> FromIterator<(K, V)> for BTree<K, V> {
+    fn from_iterator<T: Iterator<(K, V)>>(iter: &mut T) -> BTree<K, V> {
+        let mut tree = BTree::with_degree(DEFAULT_MIN_DEG);
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: TotalOrd, V> Extendable<(K, V)> for BTree<K, V> {
+    ///Inserts every pair from the iterator.  Keys that are already present
+    ///are resolved by the tree's DupPolicy, as for insert.
+    fn extend<T: Iterator<(K, V)>>(&mut self, iter: &mut T) {
+        for (k, v) in *iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+///An iterator over the key-value pairs of a BTree, in ascending key order.
+///It can also be run from the back, in descending key order.
+pub struct Entries<'a, K, V> {
+    //Each front frame holds a node and the index of the next element to
+    //yield from it; every child to the left of that element has been
+    //visited.
+    priv front: Vec<(&'a Node<K, V>, uint)>,
+    //Each back frame holds a node and the number of its elements left to
+    //yield; every child to the right of those elements has been visited.
+    priv back: Vec<(&'a Node<K, V>, uint)>,
+    //The number of entries between the two ends, so they never pass each
+    //other.
+    priv remaining: uint
+}
+
+impl<'a, K, V> Entries<'a, K, V> {
+    ///Pushes a front frame for each node down the leftmost edge of a
+    ///subtree.
+    fn push_left(&mut self, node: &'a Node<K, V>) {
+        let mut node = node;
+        loop {
+            self.front.push((node, 0));
+            match node.kind {
+                Leaf => return,
+                Branch(ref kids, _) => node = &**kids.get(0)
+            }
+        }
+    }
+
+    ///Pushes a back frame for each node down the rightmost edge of a
+    ///subtree.
+    fn push_right(&mut self, node: &'a Node<K, V>) {
+        let mut node = node;
+        loop {
+            self.back.push((node, node.elts.len()));
+            match node.kind {
+                Leaf => return,
+                Branch(ref kids, _) => node = &**kids.get(kids.len() - 1)
+            }
+        }
+    }
+}
+
+impl<'a, K: TotalOrd, V> Entries<'a, K, V> {
+    ///Pushes a front frame for each node on the path to the first element
+    ///whose key is not less than k (inclusive) or greater than k
+    ///(otherwise).
+    fn seek(&mut self, node: &'a Node<K, V>, k: &K, inclusive: bool) {
+        let mut node = node;
+        loop {
+            let index = node.bsearch_node(k);
+            if index < node.elts.len() && node.elts.get(index).key.cmp(k) == Equal {
+                if inclusive {
+                    self.front.push((node, index));
+                } else {
+                    //Skip k itself; the child to its right comes next.
+                    self.front.push((node, index + 1));
+                    match node.kind {
+                        Leaf => {}
+                        Branch(ref kids, _) => self.push_left(&**kids.get(index + 1))
+                    }
+                }
+                return;
+            }
+            self.front.push((node, index));
+            match node.kind {
+                Leaf => return,
+                Branch(ref kids, _) => node = &**kids.get(index)
+            }
+        }
+    }
+
+    ///Pushes a back frame for each node on the path to the last element
+    ///whose key is not greater than k (inclusive) or less than k
+    ///(otherwise).
+    fn seek_back(&mut self, node: &'a Node<K, V>, k: &K, inclusive: bool) {
+        let mut node = node;
+        loop {
+            let index = node.bsearch_node(k);
+            if index < node.elts.len() && node.elts.get(index).key.cmp(k) == Equal {
+                if inclusive {
+                    //Everything right of k lies above it.
+                    self.back.push((node, index + 1));
+                } else {
+                    //If k is the separator at index, its whole left child
+                    //lies below k.
+                    self.back.push((node, index));
+                    match node.kind {
+                        Leaf => {}
+                        Branch(ref kids, _) => self.push_right(&**kids.get(index))
+                    }
+                }
+                return;
+            }
+            self.back.push((node, index));
+            match node.kind {
+                Leaf => return,
+                Branch(ref kids, _) => node = &**kids.get(index)
+            }
+        }
+    }
+}
+
+impl<'a, K, V> Iterator<(&'a K, &'a V)> for Entries<'a, K, V> {
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            let (node, index) = match self.front.pop() {
+                None => return None,
+                Some(frame) => frame
+            };
+            if index < node.elts.len() {
+                let elt = node.elts.get(index);
+                //Everything in the child after this element comes next.
+                self.front.push((node, index + 1));
+                match node.kind {
+                    Leaf => {}
+                    Branch(ref kids, _) => self.push_left(&**kids.get(index + 1))
+                }
+                self.remaining -= 1;
+                return Some((&elt.key, &elt.value));
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator<(&'a K, &'a V)> for Entries<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            let (node, left) = match self.back.pop() {
+                None => return None,
+                Some(frame) => frame
+            };
+            if left > 0 {
+                let elt = node.elts.get(left - 1);
+                //Everything in the child before this element comes next.
+                self.back.push((node, left - 1));
+                match node.kind {
+                    Leaf => {}
+                    Branch(ref kids, _) => self.push_right(&**kids.get(left - 1))
+                }
+                self.remaining -= 1;
+                return Some((&elt.key, &elt.value));
+            }
+        }
+    }
+}
+
+///An iterator over the key-value pairs of a BTree, in ascending key order,
+///with the values borrowed mutably.
+pub struct MutEntries<'a, K, V> {
+    //Frames are as for Entries, but hold raw pointers: a borrowed frame
+    //would keep its whole node borrowed while the values handed out from
+    //it are still in use.  Only the children are reached through a node
+    //after it is pushed, and each value is handed out once, so no two
+    //live references overlap.
+    priv stack: Vec<(*mut Node<K, V>, uint)>,
+    //The number of entries left to yield, which ends a range early.
+    priv remaining: uint,
+    priv marker: marker::ContravariantLifetime<'a>
+}
+
+impl<'a, K, V> MutEntries<'a, K, V> {
+    ///Pushes a frame for each node down the leftmost edge of a subtree.
+    fn push_left(&mut self, node: *mut Node<K, V>) {
+        let mut node = node;
+        loop {
+            self.stack.push((node, 0));
+            let node_ref: &mut Node<K, V> = unsafe { &mut *node };
+            match node_ref.kind {
+                Leaf => return,
+                Branch(ref mut kids, _) => node = &mut **kids.get_mut(0) as *mut Node<K, V>
+            }
+        }
+    }
+}
+
+impl<'a, K: TotalOrd, V> MutEntries<'a, K, V> {
+    ///Pushes a frame for each node on the path to the first element whose
+    ///key is not less than k.
+    fn seek(&mut self, node: *mut Node<K, V>, k: &K) {
+        let mut node = node;
+        loop {
+            let node_ref: &mut Node<K, V> = unsafe { &mut *node };
+            let index = node_ref.bsearch_node(k);
+            self.stack.push((node, index));
+            if index < node_ref.elts.len() && node_ref.elts.get(index).key.cmp(k) == Equal {
+                return;
+            }
+            match node_ref.kind {
+                Leaf => return,
+                Branch(ref mut kids, _) => node = &mut **kids.get_mut(index) as *mut Node<K, V>
+            }
+        }
+    }
+}
+
+impl<'a, K, V> Iterator<(&'a K, &'a mut V)> for MutEntries<'a, K, V> {
+    fn next(&mut self) -> Option<(&'a K, &'a mut V)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            let (ptr, index) = match self.stack.pop() {
+                None => return None,
+                Some(frame) => frame
+            };
+            let node: &'a mut Node<K, V> = unsafe { &mut *ptr };
+            if index < node.elts.len() {
+                //Everything in the child after this element comes next.
+                self.stack.push((ptr, index + 1));
+                match node.kind {
+                    Leaf => {}
+                    Branch(ref mut kids, _) => {
+                        self.push_left(&mut **kids.get_mut(index + 1) as *mut Node<K, V>)
+                    }
+                }
+                self.remaining -= 1;
+                let elt = node.elts.get_mut(index);
+                return Some((&elt.key, &mut elt.value));
+            }
+        }
+    }
+}
+
+///An iterator over the keys of a BTree, in ascending order.
+pub struct Keys<'a, K, V> {
+    priv iter: Entries<'a, K, V>
+}
+
+impl<'a, K, V> Iterator<&'a K> for Keys<'a, K, V> {
+    fn next(&mut self) -> Option<&'a K> {
+        self.iter.next().map(|(k, _)| k)
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator<&'a K> for Keys<'a, K, V> {
+    fn next_back(&mut self) -> Option<&'a K> {
+        self.iter.next_back().map(|(k, _)| k)
+    }
+}
+
+///An iterator over the values of a BTree, in ascending order of their keys.
+pub struct Values<'a, K, V> {
+    priv iter: Entries<'a, K, V>
+}
+
+impl<'a, K, V> Iterator<&'a V> for Values<'a, K, V> {
+    fn next(&mut self) -> Option<&'a V> {
+        self.iter.next().map(|(_, v)| v)
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator<&'a V> for Values<'a, K, V> {
+    fn next_back(&mut self) -> Option<&'a V> {
+        self.iter.next_back().map(|(_, v)| v)
+    }
+}
+
+///An iterator over the values of a BTree, in ascending order of their keys,
+///with the values borrowed mutably.
+pub struct MutValues<'a, K, V> {
+    priv iter: MutEntries<'a, K, V>
+}
+
+impl<'a, K, V> Iterator<&'a mut V> for MutValues<'a, K, V> {
+    fn next(&mut self) -> Option<&'a mut V> {
+        self.iter.next().map(|(_, v)| v)
+    }
+}
+
+///An iterator that moves the key-value pairs out of a BTree, in ascending
+///key order.
+pub struct MoveEntries<K, V> {
+    priv stack: Vec<MoveFrame<K, V>>
+}
+
+//The elements and children of a node that MoveEntries has yet to yield.
+struct MoveFrame<K, V> {
+    elts: vec_ng::MoveItems<Elt<K, V>>,
+    children: vec_ng::MoveItems<~Node<K, V>>
+}
+
+impl<K, V> MoveEntries<K, V> {
+    ///Takes apart each node down the leftmost edge of a subtree, pushing
+    ///a frame for each.
+    fn push_left(&mut self, node: Node<K, V>) {
+        let mut node = node;
+        loop {
+            let Node { elts: elts, kind: kind } = node;
+            let mut children = match kind {
+                Leaf => Vec::new().move_iter(),
+                Branch(kids, _) => kids.move_iter()
+            };
+            let first = children.next();
+            self.stack.push(MoveFrame { elts: elts.move_iter(), children: children });
+            match first {
+                None => return,
+                Some(~child) => node = child
+            }
+        }
+    }
+}
+
+impl<K, V> Iterator<(K, V)> for MoveEntries<K, V> {
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            let (elt, child) = match self.stack.mut_last() {
+                None => return None,
+                Some(frame) => {
+                    match frame.elts.next() {
+                        None => (None, None),
+                        Some(elt) => (Some(elt), frame.children.next())
+                    }
+                }
+            };
+            match elt {
+                //This node is used up.
+                None => { self.stack.pop(); }
+                Some(Elt { key: key, value: value }) => {
+                    match child {
+                        None => {}
+                        Some(~child) => self.push_left(child)
+                    }
+                    return Some((key, value));
+                }
+            }
+        }
+    }
+}
+
+///An iterator over the keys of each node of a BTree, in level order.
+pub struct LevelOrder<'a, K, V> {
+    priv queue: RingBuf<&'a Node<K, V>>
+}
+
+impl<'a, K, V> Iterator<~[&'a K]> for LevelOrder<'a, K, V> {
+    fn next(&mut self) -> Option<~[&'a K]> {
+        let node = match self.queue.pop_front() {
+            None => return None,
+            Some(node) => node
+        };
+        match node.kind {
+            Leaf => {}
+            Branch(ref kids, _) => {
+                for kid in kids.iter() {
+                    self.queue.push_back(&**kid);
+                }
+            }
+        }
+        Some(node.elts.iter().map(|elt| &elt.key).collect())
+    }
+}
+
+///An iterator that moves the keys out of a BTree, in ascending order.
+pub struct MoveKeys<K, V> {
+    priv iter: MoveEntries<K, V>
+}
+
+impl<K, V> Iterator<K> for MoveKeys<K, V> {
+    fn next(&mut self) -> Option<K> {
+        self.iter.next().map(|(k, _)| k)
+    }
+}
+
+///An iterator that moves the values out of a BTree, in ascending order of
+///their keys.
+pub struct MoveValues<K, V> {
+    priv iter: MoveEntries<K, V>
+}
+
+impl<K, V> Iterator<V> for MoveValues<K, V> {
+    fn next(&mut self) -> Option<V> {
+        self.iter.next().map(|(_, v)| v)
+    }
+}
+
+///A view into a single key of a tree, which is either occupied or vacant,
+///returned by entry.
+pub enum Entry<'a, K, V> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>)
+}
+
+///An entry whose key is in the tree, holding on to its value.
+pub struct OccupiedEntry<'a, K, V> {
+    priv value: &'a mut V
+}
+
+///An entry whose key is not in the tree, holding on to the key, and the
+///path to where it belongs, until a value is inserted under it.
+pub struct VacantEntry<'a, K, V> {
+    priv tree: &'a mut BTree<K, V>,
+    priv key: K,
+    priv path: Vec<uint>
+}
+
+impl<'a, K: TotalOrd, V> Entry<'a, K, V> {
+    ///Returns the entry's value, first inserting default if it is vacant.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Occupied(entry) => entry.into_mut(),
+            Vacant(entry) => entry.insert(default)
+        }
+    }
+
+    ///Returns the entry's value, first inserting the value returned by f
+    ///if it is vacant.  f is only called for a vacant entry.
+    pub fn or_insert_with(self, f: || -> V) -> &'a mut V {
+        match self {
+            Occupied(entry) => entry.into_mut(),
+            Vacant(entry) => entry.insert(f())
+        }
+    }
+
+    ///Passes the value of an occupied entry to f, then returns the entry
+    ///for further use.  A vacant entry is returned as it is.
+    pub fn and_modify(self, f: |&mut V|) -> Entry<'a, K, V> {
+        match self {
+            Occupied(mut entry) => {
+                f(entry.get_mut());
+                Occupied(entry)
+            }
+            Vacant(entry) => Vacant(entry)
+        }
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    ///Returns a reference to the value.
+    pub fn get<'b>(&'b self) -> &'b V {
+        &*self.value
+    }
+
+    ///Returns a mutable reference to the value.
+    pub fn get_mut<'b>(&'b mut self) -> &'b mut V {
+        &mut *self.value
+    }
+
+    ///Returns a mutable reference to the value that lives as long as the
+    ///tree's borrow.
+    pub fn into_mut(self) -> &'a mut V {
+        self.value
+    }
+
+    ///Replaces the value, returning the old one.
+    pub fn insert(&mut self, v: V) -> V {
+        replace(&mut *self.value, v)
+    }
+}
+
+impl<'a, K: TotalOrd, V> VacantEntry<'a, K, V> {
+    ///Inserts v under the entry's key, returning a mutable reference to it.
+    pub fn insert(self, v: V) -> &'a mut V {
+        let VacantEntry { tree: tree, key: key, path: path } = self;
+        tree.len += 1;
+        tree.root.insert_at_path(path.as_slice(), Elt { key: key, value: v })
+    }
+}
+
+///An ordered set, stored as a BTree whose values are all ().
+#[deriving(Clone)]
+pub struct BTreeSet<K> {
+    priv map: BTree<K, ()>
+}
+
+impl<K: TotalOrd> BTreeSet<K> {
+    ///Returns an empty set with the default minimum degree.
+    pub fn new() -> BTreeSet<K> {
+        BTreeSet::with_degree(DEFAULT_MIN_DEG)
+    }
+
+    ///Returns an empty set with the given minimum degree.
+    ///Fails if the minimum degree is less than 2.
+    pub fn with_degree(md: uint) -> BTreeSet<K> {
+        BTreeSet { map: BTree::with_degree(md) }
+    }
+
+    ///Adds a key to the set.  Returns true if it was not already present.
+    pub fn insert(&mut self, k: K) -> bool {
+        let (_, inserted) = self.map.find_or_insert_common(k, (), |a| a);
+        inserted
+    }
+
+    ///Returns true if the set contains the key.
+    pub fn contains(&self, k: &K) -> bool {
+        self.map.contains_key(k)
+    }
+
+    ///Removes a key from the set.  Returns true if it was present.
+    pub fn remove(&mut self, k: &K) -> bool {
+        self.map.remove(k).is_some()
+    }
+
+    ///Returns an iterator over the keys of the set, in ascending order.
+    pub fn iter<'a>(&'a self) -> SetItems<'a, K> {
+        SetItems { iter: self.map.keys() }
+    }
+
+    ///Returns an iterator over the keys in this set but not in other, in
+    ///ascending order.
+    pub fn difference<'a>(&'a self, other: &'a BTreeSet<K>) -> DifferenceItems<'a, K> {
+        DifferenceItems { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    ///Returns an iterator over the keys in both this set and other, in
+    ///ascending order.
+    pub fn intersection<'a>(&'a self, other: &'a BTreeSet<K>) -> IntersectionItems<'a, K> {
+        IntersectionItems { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+
+    ///Returns an iterator over the keys in either this set or other, in
+    ///ascending order.
+    pub fn union<'a>(&'a self, other: &'a BTreeSet<K>) -> UnionItems<'a, K> {
+        UnionItems { a: self.iter().peekable(), b: other.iter().peekable() }
+    }
+}
+
+impl<K: TotalOrd> Container for BTreeSet<K> {
+    ///Returns the number of keys in the set.
+    fn len(&self) -> uint {
+        self.map.len()
+    }
+}
+
+impl<K: TotalOrd> Mutable for BTreeSet<K> {
+    ///Removes every key from the set.
+    fn clear(&mut self) {
+        self.map.clear()
+    }
+}
+
+impl<K: TotalOrd> FromIterator<K> for BTreeSet<K> {
+    fn from_iterator<T: Iterator<K>>(iter: &mut T) -> BTreeSet<K> {
+        let mut set = BTreeSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<K: TotalOrd> Extendable<K> for BTreeSet<K> {
+    fn extend<T: Iterator<K>>(&mut self, iter: &mut T) {
+        for k in *iter {
+            self.insert(k);
+        }
+    }
+}
+
+///An iterator over the keys of a BTreeSet, in ascending order.
+pub struct SetItems<'a, K> {
+    priv iter: Keys<'a, K, ()>
+}
+
+impl<'a, K> Iterator<&'a K> for SetItems<'a, K> {
+    fn next(&mut self) -> Option<&'a K> {
+        self.iter.next()
+    }
+}
+
+//Each set operation walks both sets in order at once, so it takes time
+//linear in their combined size.
+
+///An iterator over the difference of two BTreeSets, in ascending order.
+pub struct DifferenceItems<'a, K> {
+    priv a: Peekable<&'a K, SetItems<'a, K>>,
+    priv b: Peekable<&'a K, SetItems<'a, K>>
+}
+
+///An iterator over the intersection of two BTreeSets, in ascending order.
+pub struct IntersectionItems<'a, K> {
+    priv a: Peekable<&'a K, SetItems<'a, K>>,
+    priv b: Peekable<&'a K, SetItems<'a, K>>
+}
+
+///An iterator over the union of two BTreeSets, in ascending order.
+pub struct UnionItems<'a, K> {
+    priv a: Peekable<&'a K, SetItems<'a, K>>,
+    priv b: Peekable<&'a K, SetItems<'a, K>>
+}
+
+//Compares x and y, returning short if x has run out and long if y has.
+fn cmp_opt<K: TotalOrd>(x: Option<&K>, y: Option<&K>,
+                        short: Ordering, long: Ordering) -> Ordering {
+    match (x, y) {
+        (None, _) => short,
+        (_, None) => long,
+        (Some(x1), Some(y1)) => x1.cmp(y1)
+    }
+}
+
+impl<'a, K: TotalOrd> Iterator<&'a K> for DifferenceItems<'a, K> {
+    fn next(&mut self) -> Option<&'a K> {
+        loop {
+            match cmp_opt(self.a.peek(), self.b.peek(), Less, Less) {
+                Less => return self.a.next(),
+                Equal => { self.a.next(); self.b.next(); }
+                Greater => { self.b.next(); }
+            }
+        }
+    }
+}
+
+impl<'a, K: TotalOrd> Iterator<&'a K> for IntersectionItems<'a, K> {
+    fn next(&mut self) -> Option<&'a K> {
+        loop {
+            let order = match (self.a.peek(), self.b.peek()) {
+                (None, _) | (_, None) => return None,
+                (Some(a1), Some(b1)) => a1.cmp(b1)
+            };
+            match order {
+                Less => { self.a.next(); }
+                Equal => { self.b.next(); return self.a.next(); }
+                Greater => { self.b.next(); }
+            }
+        }
+    }
+}
+
+impl<'a, K: TotalOrd> Iterator<&'a K> for UnionItems<'a, K> {
+    fn next(&mut self) -> Option<&'a K> {
+        loop {
+            match cmp_opt(self.a.peek(), self.b.peek(), Greater, Less) {
+                Less => return self.a.next(),
+                Equal => { self.b.next(); return self.a.next(); }
+                Greater => return self.b.next()
+            }
+        }
+    }
+}
+
+//Stores or discards new, a value for a key already present under old, as
+//policy says, and returns the value that is not kept.
+fn resolve_dup<V>(old: &mut V, new: V, policy: DupPolicy) -> Option<V> {
+    match policy {
+        Overwrite => Some(replace(old, new)),
+        KeepFirst => Some(new)
+    }
+}
+
+//Returns true if the keys of the elements are in strictly ascending order.
+fn is_ascending<K: TotalOrd, V>(elts: &Vec<Elt<K, V>>) -> bool {
+    range(1, elts.len()).all(|i| elts.get(i - 1).key.cmp(&elts.get(i).key) == Less)
+}
+
+impl<K: TotalOrd, V> Node<K, V> {
+    ///Builds a subtree of the given height out of the next len pairs of a
+    ///sorted iterator.  Each child gets an even share of the pairs, and
+    ///there are as few children as will hold them.
+    fn build<T: Iterator<(K, V)>>(iter: &mut T, len: uint, height: uint,
+                                  min_deg: uint, is_root: bool) -> Node<K, V> {
+        if height == 1 {
+            let elts = iter.by_ref().take(len).map(|(k, v)| Elt { key: k, value: v }).collect();
+            return Node { elts: elts, kind: Leaf };
+        }
+        //The most a subtree one level down can hold.
+        let mut child_cap = max_elts_for(min_deg);
+        for _ in range(2, height) {
+            child_cap = next_level_cap_for(child_cap, min_deg);
+        }
+        let mut num_children = (len + child_cap + 1) / (child_cap + 1);
+        //Nodes below the root need at least min_deg children.
+        if !is_root && num_children < min_deg {
+            num_children = min_deg;
+        }
+        let child_elts = len - (num_children - 1);
+        let mut elts = Vec::with_capacity(num_children - 1);
+        let mut children = Vec::with_capacity(num_children);
+        for i in range(0, num_children) {
+            let mut child_len = child_elts / num_children;
+            if i < child_elts % num_children {
+                child_len += 1;
+            }
+            children.push(~Node::build(iter, child_len, height - 1, min_deg, false));
+            if i + 1 < num_children {
+                let (k, v) = iter.next().unwrap();
+                elts.push(Elt { key: k, value: v });
+            }
+        }
+        Node { elts: elts, kind: Branch(children, len) }
+    }
+
+    ///Tops up the last child of each node down the right edge of this
+    ///subtree to min_elts elements, by rotating elements in from the child
+    ///to its left.  The nodes left of the edge must be full, as in a tree
+    ///built by from_sorted_iter.
+    fn fill_right_edge(&mut self, min_elts: uint) {
+        let last = match self.kind {
+            Leaf => return,
+            Branch(ref kids, _) => kids.len() - 1
+        };
+        while self.kids().get(last).elts.len() < min_elts {
+            self.rotate_right(last);
+        }
+        self.mut_kids().get_mut(last).fill_right_edge(min_elts);
+    }
+
+    ///Gives children i and i + 1, either of which may be the root of a
+    ///tree that was just grafted on, at least min_elts elements each, by
+    ///moving elements across from the other one or, if together they are
+    ///too small for that, by merging them.
+    fn balance_kids(&mut self, i: uint, min_elts: uint) {
+        while self.kids().get(i + 1).elts.len() < min_elts &&
+              self.kids().get(i).elts.len() > min_elts {
+            self.rotate_right(i + 1);
+        }
+        while self.kids().get(i).elts.len() < min_elts &&
+              self.kids().get(i + 1).elts.len() > min_elts {
+            self.rotate_left(i);
+        }
+        if self.kids().get(i).elts.len() < min_elts ||
+           self.kids().get(i + 1).elts.len() < min_elts {
+            self.merge_children(i);
+        }
+    }
+
+    ///Returns true if this node has no children.
+    fn is_leaf(&self) -> bool {
+        match self.kind {
+            Leaf => true,
+            Branch(..) => false
+        }
+    }
+
+    ///Returns the number of elements in the subtree rooted at this node.
+    fn size(&self) -> uint {
+        match self.kind {
+            Leaf => self.elts.len(),
+            Branch(_, size) => size
+        }
+    }
+
+    ///Updates the cached size of a branch's subtree.  A leaf's size is just
+    ///the number of its elements, so this does nothing for a leaf.
+    fn set_size(&mut self, size: uint) {
+        match self.kind {
+            Leaf => {}
+            Branch(_, ref mut cached) => *cached = size
+        }
+    }
+
+    ///Recomputes the cached size of a branch from its elements and its
+    ///children's sizes.  Does nothing for a leaf.
+    fn recount_size(&mut self) {
+        let size = match self.kind {
+            Leaf => return,
+            Branch(ref kids, _) => kids.iter().fold(self.elts.len(), |n, kid| n + kid.size())
+        };
+        self.set_size(size);
+    }
+
+    ///Returns the children of a branch.  Fails on a leaf.
+    fn kids<'a>(&'a self) -> &'a Vec<~Node<K, V>> {
+        match self.kind {
+            Branch(ref kids, _) => kids,
+            Leaf => fail!("BTree: a leaf has no children")
+        }
+    }
+
+    ///Mutable counterpart of kids.
+    fn mut_kids<'a>(&'a mut self) -> &'a mut Vec<~Node<K, V>> {
+        match self.kind {
+            Branch(ref mut kids, _) => kids,
+            Leaf => fail!("BTree: a leaf has no children")
+        }
+    }
+
+    ///Counts this node and every node below it.
+    fn node_count(&self) -> uint {
+        match self.kind {
+            Leaf => 1,
+            Branch(ref kids, _) => kids.iter().fold(1, |n, kid| n + kid.node_count())
+        }
+    }
+
+    ///Sums the capacities of the element vectors in the subtree.
+    fn elt_capacity(&self) -> uint {
+        match self.kind {
+            Leaf => self.elts.capacity(),
+            Branch(ref kids, _) => {
+                kids.iter().fold(self.elts.capacity(), |n, kid| n + kid.elt_capacity())
+            }
+        }
+    }
+
+    ///Shrinks the vectors of every node in the subtree to fit their
+    ///contents.
+    fn shrink_to_fit(&mut self) {
+        self.elts.shrink_to_fit();
+        match self.kind {
+            Leaf => {}
+            Branch(ref mut kids, _) => {
+                kids.shrink_to_fit();
+                for kid in kids.mut_iter() {
+                    kid.shrink_to_fit();
+                }
+            }
+        }
+    }
+
+    ///Checks the invariants for the subtree rooted at this node, whose
+    ///keys must all lie strictly between lo and hi when they are given.
+    ///Returns the depth of the subtree's leaves, or None if any check fails.
+    fn validate(&self, min_deg: uint, is_root: bool,
+                lo: Option<&K>, hi: Option<&K>) -> Option<uint> {
+        let len = self.elts.len();
+        if (!is_root && len < min_elts_for(min_deg)) || len > max_elts_for(min_deg) {
+            return None;
+        }
+        for i in range(1, len) {
+            if self.elts.get(i - 1).key.cmp(&self.elts.get(i).key) != Less {
+                return None;
+            }
+        }
+        if len > 0 {
+            match lo {
+                Some(lo) if lo.cmp(&self.elts.get(0).key) != Less => return None,
+                _ => {}
+            }
+            match hi {
+                Some(hi) if self.elts.get(len - 1).key.cmp(hi) != Less => return None,
+                _ => {}
+            }
+        }
+        match self.kind {
+            Leaf => Some(1),
+            Branch(ref kids, _) => {
+                if kids.len() != len + 1 {
+                    return None;
+                }
+                let mut depth = None;
+                for (i, kid) in kids.iter().enumerate() {
+                    let kid_lo = if i == 0 { lo } else { Some(&self.elts.get(i - 1).key) };
+                    let kid_hi = if i == len { hi } else { Some(&self.elts.get(i).key) };
+                    let kid_depth = kid.validate(min_deg, false, kid_lo, kid_hi);
+                    if kid_depth.is_none() || (depth.is_some() && kid_depth != depth) {
+                        return None;
+                    }
+                    depth = kid_depth;
+                }
+                let size = kids.iter().fold(len, |n, kid| n + kid.size());
+                if size != self.size() {
+                    return None;
+                }
+                depth.map(|d| d + 1)
+            }
+        }
+    }
+
+    ///Splits child i if it is full.  The child keeps the left half of its
+    ///elements, the median moves up into this node at index i, and the
+    ///last len / 2 elements go to a new child at index i + 1, along with
+    ///the grandchildren to the right of the median.  A full child in a tree
+    ///has an odd number of elements, so the halves are equal; for an even
+    ///len the median is the lower of the middle two, and the new child gets
+    ///one element more than the old one keeps.  Either way, each half has
+    ///at least (len - 1) / 2 elements.
+    fn split_child(&mut self, i: uint, ub: uint) {
+        let kids = match self.kind {
+            Branch(ref mut kids, _) => kids,
+            Leaf => fail!("BTree: a leaf has no children to split")
+        };
+        let (mid_elt, new_node_right) = {
+            let child: &mut Node<K,V> = &mut **kids.get_mut(i);
+            if child.elts.len() < ub { return; }
+            let left_len = child.elts.len() - child.elts.len() / 2 - 1;
+            let mut elts = replace(&mut child.elts, Vec::new()).move_iter();
+            child.elts = elts.by_ref().take(left_len).collect();
+            let mid_elt = elts.next().unwrap();
+            let new_elts_right: Vec<Elt<K, V>> = elts.collect();
+            let new_kind_right = match child.kind {
+                Leaf => Leaf,
+                Branch(ref mut gchild, ref mut size) => {
+                    let mut gkids = replace(gchild, Vec::new()).move_iter();
+                    *gchild = gkids.by_ref().take(left_len + 1).collect();
+                    let right_kids: Vec<~Node<K, V>> = gkids.collect();
+                    let right_size = right_kids.iter().fold(new_elts_right.len(),
+                                                            |n, kid| n + kid.size());
+                    *size -= right_size + 1;
+                    Branch(right_kids, right_size)
+                }
+            };
+            (mid_elt, ~Node { elts: new_elts_right, kind: new_kind_right })
+        };
+        self.elts.insert(i, mid_elt);
+        kids.insert(i + 1, new_node_right);
+        if cfg!(not(ndebug)) {
+            assert!(is_ascending(&self.elts) && is_ascending(&kids.get(i).elts) &&
+                    is_ascending(&kids.get(i + 1).elts),
+                    "BTree::split_child: keys out of order after the split");
+        }
+    }
+
+    ///Inserts into the subtree rooted at this (non-full) node, returning
+    ///the old value if the key was already present.
+    fn insert_nonfull(&mut self, k: K, v: V, ub: uint, policy: DupPolicy) -> Option<V> {
+        let mut path = Vec::new();
+        if self.descend_to_insert(&k, ub, &mut path) {
+            resolve_dup(self.value_at_path(path.as_slice()), v, policy)
+        } else {
+            self.insert_at_path(path.as_slice(), Elt { key: k, value: v });
+            None
+        }
+    }
+
+    ///Returns the value stored under k in the subtree rooted at this
+    ///(non-full) node, inserting not_found(a) first if k is missing.  The
+    ///flag returned with the value is true if it was inserted.
+    fn find_or_insert<'a, A>(&'a mut self, k: K, a: A, not_found: |A| -> V,
+                             ub: uint) -> (&'a mut V, bool) {
+        let mut path = Vec::new();
+        if self.descend_to_insert(&k, ub, &mut path) {
+            (self.value_at_path(path.as_slice()), false)
+        } else {
+            (self.insert_at_path(path.as_slice(), Elt { key: k, value: not_found(a) }), true)
+        }
+    }
+
+    ///Walks down from this (non-full) node toward where k belongs,
+    ///splitting every full child before moving into it, so that whatever
+    ///node the walk ends in has room for one more element.  The index of
+    ///each child taken is pushed onto path, followed by the index in the
+    ///last node where k is stored or would be inserted.  Returns true if
+    ///k is already in the tree.  The walk is a loop rather than a
+    ///recursion, so a deep tree needs no more stack than a shallow one.
+    fn descend_to_insert(&mut self, k: &K, ub: uint, path: &mut Vec<uint>) -> bool {
+        let mut node = self;
+        loop {
+            let mut index = node.bsearch_node(k);
+            if index < node.elts.len() && node.elts.get(index).key.cmp(k) == Equal {
+                path.push(index);
+                return true;
+            }
+            //A leaf is where new keys go.
+            if node.is_leaf() {
+                path.push(index);
+                return false;
+            }
+            node.split_child(index, ub);
+            //The median moved up by the split may be k itself, or may now
+            //sit before it.
+            if index < node.elts.len() {
+                match node.elts.get(index).key.cmp(k) {
+                    Equal => {
+                        path.push(index);
+                        return true;
+                    }
+                    Less => index += 1,
+                    Greater => {}
+                }
+            }
+            path.push(index);
+            let tmp = node;
+            node = &mut **tmp.mut_kids().get_mut(index);
+        }
+    }
+
+    ///Follows a path recorded by descend_to_insert for a key that was
+    ///found, and returns its value.
+    fn value_at_path<'a>(&'a mut self, path: &[uint]) -> &'a mut V {
+        let mut node = self;
+        for &index in path.init().iter() {
+            let tmp = node;
+            node = &mut **tmp.mut_kids().get_mut(index);
+        }
+        &mut node.elts.get_mut(path[path.len() - 1]).value
+    }
+
+    ///Follows a path recorded by descend_to_insert for a key that was not
+    ///found, inserting elt in the leaf it ends in and counting it in the
+    ///size of every branch on the way.  Returns the inserted value.
+    fn insert_at_path<'a>(&'a mut self, path: &[uint], elt: Elt<K, V>) -> &'a mut V {
+        let mut node = self;
+        for &index in path.init().iter() {
+            let size = node.size();
+            node.set_size(size + 1);
+            let tmp = node;
+            node = &mut **tmp.mut_kids().get_mut(index);
+        }
+        let last = path[path.len() - 1];
+        node.elts.insert(last, elt);
+        if cfg!(not(ndebug)) {
+            assert!(is_ascending(&node.elts),
+                    "BTree::insert_at_path: keys out of order after the insert");
+        }
+        &mut node.elts.get_mut(last).value
+    }
+
+    ///Looks up a key in this node, descending into the appropriate child
+    ///if the key is not stored here, and returns the element holding it.
+    ///The descent is a loop, so the stack stays flat however tall the tree.
+    fn find<'a>(&'a self, k: &K) -> Option<&'a Elt<K, V>> {
+        let mut node = self;
+        loop {
+            let index = node.bsearch_node(k);
+            if index < node.elts.len() && node.elts.get(index).key.cmp(k) == Equal {
+                return Some(node.elts.get(index));
+            }
+            match node.kind {
+                //A leaf without the key means the key is not in the tree.
+                Leaf => return None,
+                Branch(ref kids, _) => node = &**kids.get(index)
+            }
+        }
+    }
+
+    ///Mutable counterpart of find.
+    fn find_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+        let index = self.bsearch_node(k);
+        if index < self.elts.len() {
+            match self.elts.get(index).key.cmp(k) {
+                Equal => { return Some(&mut self.elts.get_mut(index).value); }
+                _ => {}
+            }
+        }
+        match self.kind {
+            Leaf => None,
+            Branch(ref mut kids, _) => kids.get_mut(index).find_mut(k)
+        }
+    }
+
+    ///Removes a key from the subtree rooted at this node.  Every child we
+    ///descend into is first given at least min_deg elements, so that it can
+    ///lose one without needing to rebalance on the way back up.
+    fn remove(&mut self, k: &K, min_deg: uint) -> Option<V> {
+        self.remove_with(|key| key.cmp(k), min_deg)
+    }
+
+    ///Removes the key that f reports as Equal from the subtree rooted at
+    ///this node.  f orders a stored key relative to the one sought, as for
+    ///BTree::find_with.
+    fn remove_with(&mut self, f: |&K| -> Ordering, min_deg: uint) -> Option<V> {
+        let index = self.bsearch_node_with(|key| f(key));
+        let found = index < self.elts.len() && f(&self.elts.get(index).key) == Equal;
+        match self.kind {
+            Leaf => {
+                if !found {
+                    return None;
+                }
+                let Elt { value: value, .. } = remove_at(&mut self.elts, index);
+                Some(value)
+            }
+            Branch(..) => {
+                let ret = if !found {
+                    let index = self.fill_child(index, min_deg);
+                    self.mut_kids().get_mut(index).remove_with(|key| f(key), min_deg)
+                }
+                //The key is in this node: replace it with its predecessor or
+                //successor if either side can spare one, or else merge the two
+                //children around it and remove it from the merged child.
+                else if self.kids().get(index).elts.len() >= min_deg {
+                    let pred = self.mut_kids().get_mut(index).remove_max(min_deg);
+                    let Elt { value: value, .. } = replace(self.elts.get_mut(index), pred);
+                    Some(value)
+                }
+                else if self.kids().get(index + 1).elts.len() >= min_deg {
+                    let succ = self.mut_kids().get_mut(index + 1).remove_min(min_deg);
+                    let Elt { value: value, .. } = replace(self.elts.get_mut(index), succ);
+                    Some(value)
+                }
+                else {
+                    self.merge_children(index);
+                    self.mut_kids().get_mut(index).remove_with(|key| f(key), min_deg)
+                };
+                if ret.is_some() {
+                    let size = self.size();
+                    self.set_size(size - 1);
+                }
+                ret
+            }
+        }
+    }
+
+    ///Removes and returns the smallest element in the subtree.
+    fn remove_min(&mut self, min_deg: uint) -> Elt<K, V> {
+        match self.kind {
+            Leaf => remove_at(&mut self.elts, 0),
+            Branch(..) => {
+                let index = self.fill_child(0, min_deg);
+                let elt = self.mut_kids().get_mut(index).remove_min(min_deg);
+                let size = self.size();
+                self.set_size(size - 1);
+                elt
+            }
+        }
+    }
+
+    ///Removes and returns the largest element in the subtree.
+    fn remove_max(&mut self, min_deg: uint) -> Elt<K, V> {
+        match self.kind {
+            Leaf => self.elts.pop().unwrap(),
+            Branch(..) => {
+                let last = self.kids().len() - 1;
+                let index = self.fill_child(last, min_deg);
+                let elt = self.mut_kids().get_mut(index).remove_max(min_deg);
+                let size = self.size();
+                self.set_size(size - 1);
+                elt
+            }
+        }
+    }
+
+    ///Makes sure the child at index i has at least min_deg elements, either
+    ///by borrowing from a sibling or by merging with one.  Returns the index
+    ///of the child that now covers the range the old child covered.
+    fn fill_child(&mut self, i: uint, min_deg: uint) -> uint {
+        let num_children = self.kids().len();
+        if self.kids().get(i).elts.len() >= min_deg {
+            i
+        }
+        else if i > 0 && self.kids().get(i - 1).elts.len() >= min_deg {
+            self.rotate_right(i);
+            i
+        }
+        else if i + 1 < num_children && self.kids().get(i + 1).elts.len() >= min_deg {
+            self.rotate_left(i);
+            i
+        }
+        else if i + 1 < num_children {
+            self.merge_children(i);
+            i
+        }
+        else {
+            self.merge_children(i - 1);
+            i - 1
+        }
+    }
+
+    ///Moves the separator at i - 1 down into child i, replacing it with
+    ///the last element of child i - 1.
+    fn rotate_right(&mut self, i: uint) {
+        let kids = match self.kind {
+            Branch(ref mut kids, _) => kids,
+            Leaf => fail!("BTree: a leaf has no children to rotate")
+        };
+        //The number of elements moving from one child to the other.
+        let moved;
+        let (elt, grandchild) = {
+            let left = kids.get_mut(i - 1);
+            let elt = left.elts.pop().unwrap();
+            let grandchild = match left.kind {
+                Leaf => None,
+                Branch(ref mut gkids, _) => gkids.pop()
+            };
+            moved = grandchild.as_ref().map_or(0, |gchild| gchild.size()) + 1;
+            let size = left.size();
+            left.set_size(size - moved);
+            (elt, grandchild)
+        };
+        let sep = replace(self.elts.get_mut(i - 1), elt);
+        let right = kids.get_mut(i);
+        right.elts.unshift(sep);
+        match grandchild {
+            None => {}
+            Some(gchild) => right.mut_kids().unshift(gchild)
+        }
+        let size = right.size();
+        right.set_size(size + moved);
+    }
+
+    ///Moves the separator at i down into child i, replacing it with
+    ///the first element of child i + 1.
+    fn rotate_left(&mut self, i: uint) {
+        let kids = match self.kind {
+            Branch(ref mut kids, _) => kids,
+            Leaf => fail!("BTree: a leaf has no children to rotate")
+        };
+        //The number of elements moving from one child to the other.
+        let moved;
+        let (elt, grandchild) = {
+            let right = kids.get_mut(i + 1);
+            let elt = remove_at(&mut right.elts, 0);
+            let grandchild = match right.kind {
+                Leaf => None,
+                Branch(ref mut gkids, _) => Some(remove_at(gkids, 0))
+            };
+            moved = grandchild.as_ref().map_or(0, |gchild| gchild.size()) + 1;
+            let size = right.size();
+            right.set_size(size - moved);
+            (elt, grandchild)
+        };
+        let sep = replace(self.elts.get_mut(i), elt);
+        let left = kids.get_mut(i);
+        left.elts.push(sep);
+        match grandchild {
+            None => {}
+            Some(gchild) => left.mut_kids().push(gchild)
+        }
+        let size = left.size();
+        left.set_size(size + moved);
+    }
+
+    ///Merges child i + 1 and the separator between them into child i.
+    fn merge_children(&mut self, i: uint) {
+        let sep = remove_at(&mut self.elts, i);
+        let kids = match self.kind {
+            Branch(ref mut kids, _) => kids,
+            Leaf => fail!("BTree: a leaf has no children to merge")
+        };
+        let ~Node { elts: right_elts, kind: right_kind } = remove_at(kids, i + 1);
+        let left = kids.get_mut(i);
+        left.elts.push(sep);
+        left.elts.push_all_move(right_elts);
+        match right_kind {
+            Leaf => {}
+            Branch(gkids, right_size) => {
+                left.mut_kids().push_all_move(gkids);
+                let size = left.size();
+                left.set_size(size + right_size + 1);
+            }
+        }
+    }
+
+    ///Returns the first element in the subtree whose key is not less than k.
+    fn lower_bound<'a>(&'a self, k: &K) -> Option<&'a Elt<K, V>> {
+        let mut node = self;
+        let mut best = None;
+        loop {
+            let index = node.bsearch_node(k);
+            if index < node.elts.len() {
+                //Anything further down is smaller than this element.
+                best = Some(node.elts.get(index));
+                if node.elts.get(index).key.cmp(k) == Equal {
+                    return best;
+                }
+            }
+            match node.kind {
+                Leaf => return best,
+                Branch(ref kids, _) => node = &**kids.get(index)
+            }
+        }
+    }
+
+    ///Returns the last element in the subtree whose key is not greater than k.
+    fn floor<'a>(&'a self, k: &K) -> Option<&'a Elt<K, V>> {
+        let mut node = self;
+        let mut best = None;
+        loop {
+            let index = node.bsearch_node(k);
+            if index < node.elts.len() && node.elts.get(index).key.cmp(k) == Equal {
+                return Some(node.elts.get(index));
+            }
+            if index > 0 {
+                //Anything further down is larger than this element.
+                best = Some(node.elts.get(index - 1));
+            }
+            match node.kind {
+                Leaf => return best,
+                Branch(ref kids, _) => node = &**kids.get(index)
+            }
+        }
+    }
+
+    ///Returns the last element in the subtree whose key is less than k.
+    fn predecessor<'a>(&'a self, k: &K) -> Option<&'a Elt<K, V>> {
+        let mut node = self;
+        let mut best = None;
+        loop {
+            //Every key in the child at index is less than k, even when k is
+            //the separator at index, so the search always goes on down.
+            let index = node.bsearch_node(k);
+            if index > 0 {
+                best = Some(node.elts.get(index - 1));
+            }
+            match node.kind {
+                Leaf => return best,
+                Branch(ref kids, _) => node = &**kids.get(index)
+            }
+        }
+    }
+
+    ///Returns the first element in the subtree whose key is greater than k.
+    fn successor<'a>(&'a self, k: &K) -> Option<&'a Elt<K, V>> {
+        let mut node = self;
+        let mut best = None;
+        loop {
+            let mut index = node.bsearch_node(k);
+            if index < node.elts.len() && node.elts.get(index).key.cmp(k) == Equal {
+                index += 1;
+            }
+            if index < node.elts.len() {
+                best = Some(node.elts.get(index));
+            }
+            match node.kind {
+                Leaf => return best,
+                Branch(ref kids, _) => node = &**kids.get(index)
+            }
+        }
+    }
+
+    ///Returns the index of the first element whose key is not less than k,
+    ///or the number of elements if every key is less than k.  This is where
+    ///k is stored if this node holds it, and where it would be inserted if not.
+    fn bsearch_node(&self, k: &K) -> uint {
+        self.bsearch_node_with(|key| key.cmp(k))
+    }
+
+    ///Returns the index of the first element whose key f does not report
+    ///as Less, or the number of elements if f reports every key as Less.
+    fn bsearch_node_with(&self, f: |&K| -> Ordering) -> uint {
+        let mut min = 0;
+        let mut max = self.elts.len();
+        //Every key before min is less than the target; no key from max on is.
+        while min < max {
+            let mid = min + (max - min) / 2;
+            match f(&self.elts.get(mid).key) {
+                Less => { min = mid + 1; }
+                _ => { max = mid; }
+            }
+        }
+        min
+    }
+}
+
+impl<K: Clone, V> Node<K, V> {
+    ///Copies the subtree rooted at this node, passing each value through f.
+    fn map_values<W>(&self, f: |&V| -> W) -> Node<K, W> {
+        let elts = self.elts.iter().map(|elt| Elt { key: elt.key.clone(), value: f(&elt.value) })
+            .collect();
+        let kind = match self.kind {
+            Leaf => Leaf,
+            Branch(ref kids, size) => {
+                Branch(kids.iter().map(|kid| ~kid.map_values(|v| f(v))).collect(), size)
+            }
+        };
+        Node { elts: elts, kind: kind }
+    }
+}
+
+impl<K: Clone, V: Clone> Clone for Node<K, V> {
+    fn clone(&self) -> Node<K, V> {
+        Node { elts: self.elts.clone(), kind: self.kind.clone() }
+    }
+
+    ///Overwrites this subtree with a copy of source's, keeping this node's
+    ///element and child vectors, and the children themselves, as far as
+    ///source has elements and children to fill them with.
+    fn clone_from(&mut self, source: &Node<K, V>) {
+        self.elts.truncate(source.elts.len());
+        for (elt, src) in self.elts.mut_iter().zip(source.elts.iter()) {
+            elt.key.clone_from(&src.key);
+            elt.value.clone_from(&src.value);
+        }
+        let reused = self.elts.len();
+        for src in source.elts.as_slice().slice_from(reused).iter() {
+            self.elts.push(src.clone());
+        }
+        match source.kind {
+            Leaf => self.kind = Leaf,
+            Branch(ref src_kids, size) => {
+                let mut kids = match replace(&mut self.kind, Leaf) {
+                    Branch(kids, _) => kids,
+                    Leaf => Vec::with_capacity(src_kids.len())
+                };
+                kids.truncate(src_kids.len());
+                for (kid, src) in kids.mut_iter().zip(src_kids.iter()) {
+                    (**kid).clone_from(&**src);
+                }
+                let reused = kids.len();
+                for src in src_kids.as_slice().slice_from(reused).iter() {
+                    kids.push(src.clone());
+                }
+                self.kind = Branch(kids, size);
+            }
+        }
+    }
+}
+
+//The recursive lookup that Node::find's loop replaced, kept so that the
+//tests can check the loop against it and the benchmarks can time the two.
+#[cfg(test)]
+fn find_recursive<'a, K: TotalOrd, V>(node: &'a Node<K, V>, k: &K) -> Option<&'a V> {
+    let index = node.bsearch_node(k);
+    if index < node.elts.len() && node.elts.get(index).key.cmp(k) == Equal {
+        return Some(&node.elts.get(index).value);
+    }
+    match node.kind {
+        Leaf => None,
+        Branch(ref kids, _) => find_recursive(&**kids.get(index), k)
+    }
+}
+
+#[cfg(test)]
+mod test_btree {
+    use super::{BTree, Node, Elt, Leaf, Branch, optimal_degree_for, next_level_cap_for};
+    use super::find_recursive;
+    use super::{Bound, Included, Excluded, Unbounded, BorrowKey};
+    use super::{Overwrite, KeepFirst, Occupied, Vacant};
+    use HashMap;
+
+    use std::default::Default;
+    use std::hash;
+    use std::mem::{size_of, swap};
+    use std::rand::Rng;
+    use std::rand;
+    use std::uint;
+    use std::vec_ng::Vec;
+
+    #[test]
+    fn split_child_test_1() {
+        let mut new_node = Node { elts: vec!(Elt { key: 3, value: ~"a" },
+                                          Elt { key: 7, value: ~"b" }),
+                                  kind: Branch(vec!(~Node { elts: vec!(Elt { key: 1, value: ~"c" },
+                                                                   Elt { key: 2, value: ~"d" }),
+                                                           kind: Leaf },
+                                                   ~Node { elts: vec!(Elt { key: 4, value: ~"e" },
+                                                                   Elt { key: 5, value: ~"f" },
+                                                                   Elt { key: 6, value: ~"g" }),
+                                                           kind: Leaf },
+                                                   ~Node { elts: vec!(Elt { key: 8, value: ~"h" },
+                                                                   Elt { key: 9, value: ~"i" }),
+                                                           kind: Leaf }), 9)};
+        new_node.split_child(1, 2);
+        assert_eq!(new_node.elts.get(1).key, 5);
+    }
+
+    #[test]
+    fn split_child_test_2() {
+        let mut new_node = Node { elts: vec!(Elt { key: 3, value: ~"a" },
+                                          Elt { key: 7, value: ~"b" }),
+                                  kind: Branch(vec!(~Node { elts: vec!(Elt { key: 1, value: ~"c" },
+                                                                   Elt { key: 2, value: ~"d" }),
+                                                           kind: Leaf },
+                                                   ~Node { elts: vec!(Elt { key: 4, value: ~"e" },
+                                                                   Elt { key: 5, value: ~"f" },
+                                                                   Elt { key: 6, value: ~"g" }),
+                                                           kind: Leaf },
+                                                   ~Node { elts: vec!(Elt { key: 8, value: ~"h" },
+                                                                   Elt { key: 9, value: ~"i" }),
+                                                           kind: Leaf }), 9)};
+        new_node.split_child(1, 2);
+        assert_eq!(new_node.kids().get(1).elts.get(0).key, 4);
+    }
+
+    #[test]
+    fn split_child_test_3() {
+        let mut new_node = Node { elts: vec!(Elt { key: 3, value: ~"a" },
+                                          Elt { key: 11, value: ~"b" }),
+                                  kind: Branch(vec!(~Node { elts: vec!(Elt { key: 1, value: ~"c" },
+                                                                   Elt { key: 2, value: ~"d" }),
+                                                           kind: Leaf },
+                                                   ~Node { elts: vec!(Elt { key: 5, value: ~"e" },
+                                                                   Elt { key: 7, value: ~"f" },
+                                                                   Elt { key: 9, value: ~"g" }),
+                                                           kind: Branch(vec!(~Node { elts: vec!(Elt { key: 4, value: ~"h" }),
+                                                                                    kind: Leaf },
+                                                                            ~Node { elts: vec!(Elt { key: 6, value: ~"i" }),
+                                                                                    kind: Leaf },
+                                                                            ~Node { elts: vec!(Elt { key: 8, value: ~"j" }),
+                                                                                    kind: Leaf },
+                                                                            ~Node { elts: vec!(Elt { key: 10, value: ~"k" }),
+                                                                                    kind: Leaf }), 7)},
+                                                   ~Node { elts: vec!(Elt { key: 12, value: ~"l" },
+                                                                   Elt { key: 13, value: ~"m" }),
+                                                           kind: Leaf }), 13)};
+        new_node.split_child(1, 2);
+        assert_eq!(new_node.kids().get(1).kids().get(0).elts.get(0).key, 4);
+    }
+
+    //A full five-element branch splits around its third element, and its
+    //six children are shared three and three.
+    #[test]
+    fn split_child_odd_test() {
+        let leaf = |k: int| ~Node { elts: vec!(Elt { key: k, value: () }), kind: Leaf };
+        let full = ~Node { elts: range(1, 6).map(|k| Elt { key: k * 10, value: () }).collect(),
+                           kind: Branch(range(0, 6).map(|k| leaf(k * 10 + 5)).collect(), 11) };
+        let mut new_node = Node { elts: vec!(), kind: Branch(vec!(full), 11) };
+        new_node.split_child(0, 5);
+        assert_eq!(new_node.elts.len(), 1);
+        assert_eq!(new_node.elts.get(0).key, 30);
+        let kids = new_node.kids();
+        assert_eq!(kids.len(), 2);
+        assert_eq!(kids.get(0).elts.iter().map(|e| e.key).collect::<~[int]>(), ~[10, 20]);
+        assert_eq!(kids.get(1).elts.iter().map(|e| e.key).collect::<~[int]>(), ~[40, 50]);
+        assert_eq!(kids.get(0).kids().iter().map(|c| c.elts.get(0).key).collect::<~[int]>(),
+                   ~[5, 15, 25]);
+        assert_eq!(kids.get(1).kids().iter().map(|c| c.elts.get(0).key).collect::<~[int]>(),
+                   ~[35, 45, 55]);
+    }
+
+    //A four-element child splits around its second element: one element
+    //stays on the left, two go right, and the grandchildren follow.
+    #[test]
+    fn split_child_even_test() {
+        let leaf_of = |ks: &[int]| ~Node { elts: ks.iter().map(|&k| Elt { key: k, value: () })
+                                                 .collect(),
+                                           kind: Leaf };
+        let mut new_node = Node { elts: vec!(), kind: Branch(vec!(leaf_of(&[1, 2, 3, 4])), 4) };
+        new_node.split_child(0, 4);
+        assert_eq!(new_node.elts.iter().map(|e| e.key).collect::<~[int]>(), ~[2]);
+        assert_eq!(new_node.kids().get(0).elts.iter().map(|e| e.key).collect::<~[int]>(), ~[1]);
+        assert_eq!(new_node.kids().get(1).elts.iter().map(|e| e.key).collect::<~[int]>(),
+                   ~[3, 4]);
+        assert_eq!(new_node.size(), 4);
+
+        let full = ~Node { elts: range(1, 5).map(|k| Elt { key: k * 10, value: () }).collect(),
+                           kind: Branch(range(0, 5).map(|k| leaf_of(&[k * 10 + 5])).collect(),
+                                        9) };
+        let mut new_node = Node { elts: vec!(), kind: Branch(vec!(full), 9) };
+        new_node.split_child(0, 4);
+        assert_eq!(new_node.elts.iter().map(|e| e.key).collect::<~[int]>(), ~[20]);
+        let kids = new_node.kids();
+        assert_eq!(kids.get(0).elts.iter().map(|e| e.key).collect::<~[int]>(), ~[10]);
+        assert_eq!(kids.get(1).elts.iter().map(|e| e.key).collect::<~[int]>(), ~[30, 40]);
+        assert_eq!(kids.get(0).kids().iter().map(|c| c.elts.get(0).key).collect::<~[int]>(),
+                   ~[5, 15]);
+        assert_eq!(kids.get(1).kids().iter().map(|c| c.elts.get(0).key).collect::<~[int]>(),
+                   ~[25, 35, 45]);
+        assert_eq!(kids.get(0).size(), 3);
+        assert_eq!(kids.get(1).size(), 5);
+        for kid in kids.iter() {
+            assert!(kid.elts.len() >= (4 - 1) / 2);
+        }
+    }
+
+    //A leaf whose keys are already out of order puts the new key after
+    //them, and the check after the insert catches it.
+    #[test]
+    #[should_fail]
+    #[cfg(not(ndebug))]
+    fn insert_out_of_order_test() {
+        let mut new_node = Node { elts: vec!(Elt { key: 5, value: () }, Elt { key: 1, value: () }),
+                                  kind: Leaf };
+        new_node.insert_nonfull(3, (), 3, Overwrite);
+    }
+
+    //A child whose median is greater than the separator after it leaves
+    //the parent out of order once the median moves up.
+    #[test]
+    #[should_fail]
+    #[cfg(not(ndebug))]
+    fn split_out_of_order_test() {
+        let full = ~Node { elts: vec!(Elt { key: 1, value: () }, Elt { key: 12, value: () },
+                                      Elt { key: 3, value: () }),
+                           kind: Leaf };
+        let right = ~Node { elts: vec!(Elt { key: 11, value: () }), kind: Leaf };
+        let mut new_node = Node { elts: vec!(Elt { key: 10, value: () }),
+                                  kind: Branch(vec!(full, right), 5) };
+        new_node.split_child(0, 3);
+    }
+
+    #[test]
+    fn bsearch_empty_test() {
+        let new_node: Node<int, ~str> = Node { elts: vec!(), kind: Leaf };
+        assert_eq!(new_node.bsearch_node(&3), 0);
+    }
+
+    //bsearch_node must agree with a linear scan for the first key >= k.
+    #[test]
+    fn bsearch_random_test() {
+        let mut rng = rand::task_rng();
+        for _ in range(0, 200) {
+            let len = rng.gen_range(0u, 12);
+            let mut keys = ~[];
+            let mut key = rng.gen_range(-5, 5);
+            for _ in range(0, len) {
+                keys.push(key);
+                key += rng.gen_range(1, 4);
+            }
+            let new_node = Node { elts: keys.iter().map(|&k| Elt { key: k, value: () }).collect(),
+                                  kind: Leaf };
+            for k in range(-8, key + 3) {
+                let expected = keys.iter().position(|&x| x >= k).unwrap_or(keys.len());
+                assert_eq!(new_node.bsearch_node(&k), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn insert_test_1() {
+        let mut new_tree = BTree { root: Node { elts: vec!(Elt { key: 1, value: ~"a" },
+                                                        Elt { key: 3, value: ~"c" }),
+                                                kind: Leaf },
+                                   len: 2,
+                                   height: 1,
+                                   min_deg: 2, dup_policy: Overwrite };
+        new_tree.insert(2, ~"b");
+        assert_eq!(new_tree.root.elts.get(1).key, 2);
+    }
+
+    #[test]
+    fn insert_test_2() {
+        let mut new_tree = BTree { root: Node { elts: vec!(Elt { key: 1, value: ~"a" },
+                                                        Elt { key: 2, value: ~"b" }),
+                                                kind: Leaf },
+                                   len: 2,
+                                   height: 1,
+                                   min_deg: 2, dup_policy: Overwrite };
+        new_tree.insert(3, ~"c");
+        assert_eq!(new_tree.root.elts.get(2).key, 3);
+    }
+
+    #[test]
+    fn insert_test_3() {
+        let mut new_tree = BTree { root: Node { elts: vec!(Elt { key: 2, value: ~"b" },
+                                                        Elt { key: 3, value: ~"c" }),
+                                                kind: Leaf },
+                                   len: 2,
+                                   height: 1,
+                                   min_deg: 2, dup_policy: Overwrite };
+        new_tree.insert(1, ~"a");
+        assert_eq!(new_tree.root.elts.get(0).key, 1);
+    }
+
+    #[test]
+    fn insert_test_4() {
+        let mut new_tree = BTree { root: Node { elts: vec!(Elt { key: 1, value: ~"a" },
+                                                        Elt { key: 2, value: ~"b" },
+                                                        Elt { key: 3, value: ~"c" },
+                                                        Elt { key: 4, value: ~"d" }),
+                                                kind: Leaf },
+                                   len: 4,
+                                   height: 1,
+                                   min_deg: 2, dup_policy: Overwrite };
+        new_tree.insert(5, ~"3");
+        assert_eq!(new_tree.root.elts.get(0).key, 2);
+    }
+
+    #[test]
+    fn insert_test_5() {
+        let mut new_node = Node { elts: vec!(Elt { key: 2, value: ~"a" },
+                                          Elt { key: 8, value: ~"b" }),
+                                  kind: Branch(vec!(~Node { elts: vec!(Elt { key: 0, value: ~"c" },
+                                                                   Elt { key: 1, value: ~"d" }),
+                                                           kind: Leaf },
+                                                   ~Node { elts: vec!(Elt { key: 3, value: ~"x" },
+                                                                   Elt { key: 4, value: ~"e" },
+                                                                   Elt { key: 6, value: ~"f" },
+                                                                   Elt { key: 7, value: ~"g" }),
+                                                           kind: Leaf },
+                                                   ~Node { elts: vec!(Elt { key: 9, value: ~"h" },
+                                                                   Elt { key: 10, value: ~"i" }),
+                                                           kind: Leaf }), 10)};
+        let mut new_tree = BTree { root: new_node,
+                                   len: 10,
+                                   height: 2,
+                                   min_deg: 2, dup_policy: Overwrite };
+        new_tree.insert(5, ~"omg");
+        assert_eq!(new_tree.root.elts.get(1).key, 4);
+    }
+
+    //Splitting a full root moves its median up into a new root, with the
+    //old root's halves (and their children) below it.
+    #[test]
+    fn insert_root_split_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(1, 4) {
+            new_tree.insert(i, i);
+        }
+        new_tree.insert(4, 4);
+        assert_eq!(new_tree.root.elts.len(), 1);
+        assert_eq!(new_tree.root.elts.get(0).key, 2);
+        {
+            let kids = new_tree.root.kids();
+            assert_eq!(kids.len(), 2);
+            assert_eq!(kids.get(0).elts.iter().map(|e| e.key).collect::<~[int]>(), ~[1]);
+            assert_eq!(kids.get(1).elts.iter().map(|e| e.key).collect::<~[int]>(), ~[3, 4]);
+        }
+        //Split a root that has children of its own.
+        for i in range(5, 10) {
+            new_tree.insert(i, i);
+        }
+        assert_eq!(new_tree.root.elts.iter().map(|e| e.key).collect::<~[int]>(), ~[4]);
+        assert_eq!(new_tree.height(), 3);
+        assert!(new_tree.validate());
+        let keys: ~[int] = new_tree.keys().map(|k| *k).collect();
+        assert_eq!(keys, range(1, 10).collect::<~[int]>());
+    }
+
+    //A tall tree of the smallest degree, filled from both ends so it keeps
+    //splitting along its edges.
+    #[test]
+    fn insert_deep_tree_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 50000) {
+            let k = if i % 2 == 0 { i } else { 100000 - i };
+            assert_eq!(new_tree.insert(k, i), None);
+        }
+        assert_eq!(new_tree.len(), 50000);
+        assert!(new_tree.height() >= 8);
+        assert!(new_tree.validate());
+        assert_eq!(new_tree.insert(0, -1), Some(0));
+        assert_eq!(new_tree.insert(99999, -1), Some(1));
+        assert_eq!(new_tree.len(), 50000);
+        assert!(new_tree.validate());
+        let mut prev = -1;
+        for (k, _) in new_tree.iter() {
+            assert!(*k > prev);
+            prev = *k;
+        }
+    }
+
+    #[test]
+    fn len_test() {
+        let mut new_tree = BTree::new(1, ~"a", 2);
+        assert_eq!(new_tree.len(), 1);
+        assert!(!new_tree.is_empty());
+        for i in range(2, 20) {
+            new_tree.insert(i, ~"x");
+        }
+        assert_eq!(new_tree.len(), 19);
+        //Overwriting existing keys must not change the length.
+        new_tree.insert(1, ~"b");
+        new_tree.insert(19, ~"z");
+        assert_eq!(new_tree.len(), 19);
+        new_tree.remove(&5);
+        new_tree.remove(&5);
+        assert_eq!(new_tree.len(), 18);
+        for i in range(1, 20) {
+            new_tree.remove(&i);
+        }
+        assert_eq!(new_tree.len(), 0);
+        assert!(new_tree.is_empty());
+    }
+
+    #[test]
+    fn iter_test() {
+        let mut rng = rand::task_rng();
+        let keys = rng.shuffle(range(0, 200).collect::<~[int]>());
+        let mut new_tree = BTree::new(keys[0], -keys[0], 3);
+        for k in keys.iter() {
+            new_tree.insert(*k, -*k);
+        }
+        let mut expected = 0;
+        for (k, v) in new_tree.iter() {
+            assert_eq!(*k, expected);
+            assert_eq!(*v, -expected);
+            expected += 1;
+        }
+        assert_eq!(expected, 200);
+    }
+
+    #[test]
+    fn mut_iter_test() {
+        let mut rng = rand::task_rng();
+        let keys = rng.shuffle(range(0, 200).collect::<~[int]>());
+        let mut new_tree = BTree::with_degree(3);
+        for k in keys.iter() {
+            new_tree.insert(*k, *k);
+        }
+        let mut count = 0;
+        for (k, v) in new_tree.mut_iter() {
+            assert_eq!(*k, count);
+            *v *= 2;
+            count += 1;
+        }
+        assert_eq!(count, 200);
+        for (k, v) in new_tree.iter() {
+            assert_eq!(*v, *k * 2);
+        }
+        let mut empty_tree: BTree<int, int> = BTree::with_degree(2);
+        assert!(empty_tree.mut_iter().next().is_none());
+    }
+
+    #[test]
+    fn range_mut_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 100) {
+            new_tree.insert(i, i);
+        }
+        for (k, v) in new_tree.range_mut(&25, &60) {
+            assert!(*k >= 25 && *k < 60);
+            *v += 1000;
+        }
+        for (k, v) in new_tree.iter() {
+            if *k >= 25 && *k < 60 {
+                assert_eq!(*v, *k + 1000);
+            } else {
+                assert_eq!(*v, *k);
+            }
+        }
+        assert!(new_tree.range_mut(&60, &25).next().is_none());
+        assert!(new_tree.range_mut(&100, &200).next().is_none());
+        assert_eq!(new_tree.range_mut(&-10, &10).count(|_| true), 10);
+    }
+
+    #[test]
+    fn mut_values_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 50) {
+            new_tree.insert(i, i + 1);
+        }
+        for v in new_tree.mut_values() {
+            *v = 0;
+        }
+        assert!(new_tree.values().all(|v| *v == 0));
+        assert_eq!(new_tree.values().count(|_| true), 50);
+    }
+
+    #[test]
+    fn rev_iter_test() {
+        let mut rng = rand::task_rng();
+        let keys = rng.shuffle(range(0, 200).collect::<~[int]>());
+        let mut new_tree = BTree::new(keys[0], -keys[0], 3);
+        for k in keys.iter() {
+            new_tree.insert(*k, -*k);
+        }
+        assert!(new_tree.height() > 2);
+        let mut expected = 200;
+        for (k, v) in new_tree.rev_iter() {
+            expected -= 1;
+            assert_eq!(*k, expected);
+            assert_eq!(*v, -expected);
+        }
+        assert_eq!(expected, 0);
+    }
+
+    //Alternates between the two ends until they meet; each key must come
+    //out exactly once, from whichever end reaches it first.
+    #[test]
+    fn double_ended_test() {
+        let mut new_tree = BTree::new(0, 0, 2);
+        for i in range(1, 100) {
+            new_tree.insert(i, i);
+        }
+        let mut iter = new_tree.iter();
+        let mut front = 0;
+        let mut back = 100;
+        loop {
+            match iter.next() {
+                None => break,
+                Some((k, _)) => { assert_eq!(*k, front); front += 1; }
+            }
+            if front % 3 == 0 { continue }
+            match iter.next_back() {
+                None => break,
+                Some((k, _)) => { back -= 1; assert_eq!(*k, back); }
+            }
+        }
+        assert_eq!(front, back);
+        assert!(iter.next().is_none());
+        assert!(iter.next_back().is_none());
+    }
+
+    //Stops the front at every possible key and drains the rest from the
+    //back.
+    #[test]
+    fn double_ended_meet_test() {
+        let mut new_tree = BTree::new(0, 0, 2);
+        for i in range(1, 30) {
+            new_tree.insert(i, i);
+        }
+        for split in range(0, 31) {
+            let mut iter = new_tree.iter();
+            let front: ~[int] = iter.by_ref().take(split).map(|(k, _)| *k).collect();
+            let back: ~[int] = iter.rev().map(|(k, _)| *k).collect();
+            assert_eq!(front, range(0, split as int).collect::<~[int]>());
+            assert_eq!(back, range(split as int, 30).rev().collect::<~[int]>());
+        }
+    }
+
+    #[test]
+    fn iter_empty_test() {
+        let mut new_tree = BTree::new(1, (), 2);
+        new_tree.remove(&1);
+        assert!(new_tree.iter().next().is_none());
+    }
+
+    #[test]
+    fn keys_values_test() {
+        let mut new_tree = BTree::new(50, ~"50", 2);
+        for i in range(0, 50) {
+            new_tree.insert(i * 31 % 50, (i * 31 % 50).to_str());
+        }
+        let keys: ~[int] = new_tree.keys().map(|k| *k).collect();
+        let values: ~[~str] = new_tree.values().map(|v| v.clone()).collect();
+        assert_eq!(keys, range(0, 51).collect::<~[int]>());
+        assert_eq!(values, range(0, 51).map(|i| i.to_str()).collect::<~[~str]>());
+    }
+
+    #[test]
+    fn map_values_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 60) {
+            new_tree.insert(i * 7 % 60, i * 7 % 60);
+        }
+        let strings = new_tree.map_values(|v| v.to_str());
+        assert!(strings.validate());
+        assert_eq!(strings.len(), 60);
+        assert_eq!(strings.node_count(), new_tree.node_count());
+        assert_eq!(strings.level_stats(), new_tree.level_stats());
+        let pairs: ~[(int, ~str)] = strings.iter().map(|(k, v)| (*k, v.clone())).collect();
+        assert_eq!(pairs, range(0, 60).map(|i| (i, i.to_str())).collect::<~[(int, ~str)]>());
+    }
+
+    #[test]
+    fn to_vec_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 60) {
+            let k = i * 23 % 60;
+            new_tree.insert(k, k.to_str());
+        }
+        assert!(new_tree.height() > 2);
+        let expected: ~[(int, ~str)] = range(0, 60).map(|i| (i, i.to_str())).collect();
+        assert_eq!(new_tree.to_vec(), expected);
+        assert_eq!(new_tree.len(), 60);
+        let empty_tree: BTree<int, ~str> = BTree::with_degree(2);
+        assert!(empty_tree.to_vec().is_empty());
+    }
+
+    #[test]
+    fn move_iter_test() {
+        let mut new_tree = BTree::new(0, ~"0", 2);
+        for i in range(1, 40) {
+            let k = i * 13 % 40;
+            new_tree.insert(k, k.to_str());
+        }
+        let pairs: Vec<(int, ~str)> = new_tree.move_iter().collect();
+        let expected: Vec<(int, ~str)> = range(0, 40).map(|i| (i, i.to_str())).collect();
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn into_keys_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 40) {
+            let k = i * 13 % 40;
+            new_tree.insert(k.to_str(), k);
+        }
+        let keys: Vec<~str> = new_tree.into_keys().collect();
+        let mut expected: ~[~str] = range(0, 40).map(|i| i.to_str()).collect();
+        expected.sort();
+        assert_eq!(keys.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn into_values_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 40) {
+            let k = i * 13 % 40;
+            new_tree.insert(k, k.to_str());
+        }
+        let values: Vec<~str> = new_tree.into_values().collect();
+        let expected: Vec<~str> = range(0, 40).map(|i| i.to_str()).collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn drain_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 40) {
+            new_tree.insert(i, i.to_str());
+        }
+        {
+            let mut iter = new_tree.drain();
+            for i in range(0, 20) {
+                assert_eq!(iter.next(), Some((i, i.to_str())));
+            }
+        }
+        assert!(new_tree.is_empty());
+        assert!(new_tree.iter().next().is_none());
+        assert!(new_tree.validate());
+        new_tree.insert(1, ~"1");
+        assert_eq!(new_tree.len(), 1);
+        assert_eq!(new_tree.min_deg, 2);
+    }
+
+    fn range_keys(new_tree: &BTree<int, int>, lo: int, hi: int) -> ~[int] {
+        new_tree.range(&lo, &hi).map(|(k, _)| *k).collect()
+    }
+
+    #[test]
+    fn range_values_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 50) {
+            new_tree.insert(i * 2, i.to_str());
+        }
+        assert!(new_tree.height() > 2);
+        assert_eq!(new_tree.range_values(&10, &20), ~[&~"5", &~"6", &~"7", &~"8", &~"9"]);
+        assert_eq!(new_tree.range_values(&-5, &3), ~[&~"0", &~"1"]);
+        assert_eq!(new_tree.range_values(&95, &200), ~[&~"48", &~"49"]);
+        let all: ~[~str] = range(0, 50).map(|i| i.to_str()).collect();
+        let expected: ~[&~str] = all.iter().collect();
+        assert_eq!(new_tree.range_values(&0, &100), expected);
+        assert!(new_tree.range_values(&13, &14).is_empty());
+        assert!(new_tree.range_values(&20, &10).is_empty());
+        assert!(new_tree.range_values(&10, &10).is_empty());
+    }
+
+    #[test]
+    fn fold_range_test() {
+        let mut rng = rand::task_rng();
+        let mut new_tree = BTree::with_degree(3);
+        for i in range(0, 500) {
+            new_tree.insert(i, rng.gen_range(0, 1000));
+        }
+        let sum = new_tree.fold_range(&100, &300, 0, |acc, v| acc + *v);
+        let mut expected = 0;
+        for i in range(100, 300) {
+            expected += *new_tree.find(&i).unwrap();
+        }
+        assert_eq!(sum, expected);
+        let max = new_tree.fold_range(&0, &500, None, |acc: Option<int>, v| {
+            match acc {
+                Some(m) if m >= *v => Some(m),
+                _ => Some(*v)
+            }
+        });
+        assert_eq!(max, new_tree.values().max().map(|v| *v));
+        let values = new_tree.fold_range(&10, &15, ~[], |mut acc, v| { acc.push(*v); acc });
+        assert_eq!(values, range(10, 15).map(|i| *new_tree.find(&i).unwrap()).collect::<~[int]>());
+        assert_eq!(new_tree.fold_range(&300, &100, 7, |acc, v| acc + *v), 7);
+    }
+
+    #[test]
+    fn range_rev_test() {
+        let mut new_tree = BTree::new(0, 0, 2);
+        for i in range(1, 50) {
+            new_tree.insert(i * 2, i);
+        }
+        for lo in range(-2, 102) {
+            for hi in range(lo, 102) {
+                let keys: ~[int] = new_tree.range(&lo, &hi).rev().map(|(k, _)| *k).collect();
+                let mut expected = range_keys(&new_tree, lo, hi);
+                expected.reverse();
+                assert_eq!(keys, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn range_test() {
+        let mut new_tree = BTree::new(0, 0, 2);
+        for i in range(1, 50) {
+            new_tree.insert(i * 2, i);
+        }
+        assert_eq!(range_keys(&new_tree, 10, 20), ~[10, 12, 14, 16, 18]);
+        assert_eq!(range_keys(&new_tree, 11, 21), ~[12, 14, 16, 18, 20]);
+        assert_eq!(range_keys(&new_tree, -5, 3), ~[0, 2]);
+        assert_eq!(range_keys(&new_tree, 95, 200), ~[96, 98]);
+        assert_eq!(range_keys(&new_tree, 0, 100), range(0, 50).map(|i| i * 2).collect::<~[int]>());
+        assert_eq!(range_keys(&new_tree, 13, 14), ~[]);
+        assert_eq!(range_keys(&new_tree, 10, 10), ~[]);
+        assert_eq!(range_keys(&new_tree, 20, 10), ~[]);
+        assert_eq!(range_keys(&new_tree, 100, 120), ~[]);
+    }
+
+    #[test]
+    fn iter_from_test() {
+        let mut new_tree = BTree::with_degree(2);
+        assert!(new_tree.iter_from(&0).next().is_none());
+        for i in range(0, 60) {
+            new_tree.insert(i * 2, i);
+        }
+        for k in range(-2, 122) {
+            let keys: ~[int] = new_tree.iter_from(&k).map(|(k, _)| *k).collect();
+            let expected: ~[int] = new_tree.iter().skip_while(|&(key, _)| *key < k)
+                .map(|(k, _)| *k).collect();
+            assert_eq!(keys, expected);
+        }
+        assert_eq!(new_tree.iter_from(&50).next(), Some((&50, &25)));
+        assert_eq!(new_tree.iter_from(&51).next(), Some((&52, &26)));
+        assert_eq!(new_tree.iter_from(&51).rev().next(), Some((&118, &59)));
+        assert!(new_tree.iter_from(&119).next().is_none());
+    }
+
+    fn in_bounds(k: int, lo: Bound<&int>, hi: Bound<&int>) -> bool {
+        let above = match lo {
+            Included(&b) => k >= b,
+            Excluded(&b) => k > b,
+            Unbounded => true
+        };
+        let below = match hi {
+            Included(&b) => k <= b,
+            Excluded(&b) => k < b,
+            Unbounded => true
+        };
+        above && below
+    }
+
+    fn bound_keys(new_tree: &BTree<int, int>, lo: Bound<&int>, hi: Bound<&int>) -> ~[int] {
+        new_tree.range_bounds(lo, hi).map(|(k, _)| *k).collect()
+    }
+
+    #[test]
+    fn range_bounds_test() {
+        let mut new_tree = BTree::new(0, 0, 2);
+        for i in range(1, 50) {
+            new_tree.insert(i * 2, i);
+        }
+        for lo in range(-2, 102) {
+            for hi in range(lo - 2, 102) {
+                let los = [Included(&lo), Excluded(&lo), Unbounded];
+                let his = [Included(&hi), Excluded(&hi), Unbounded];
+                for &l in los.iter() {
+                    for &h in his.iter() {
+                        let expected: ~[int] = range(0, 50).map(|i| i * 2)
+                            .filter(|&k| in_bounds(k, l, h)).collect();
+                        assert_eq!(bound_keys(&new_tree, l, h), expected);
+                        let mut back: ~[int] =
+                            new_tree.range_bounds(l, h).rev().map(|(k, _)| *k).collect();
+                        back.reverse();
+                        assert_eq!(back, expected);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn range_bounds_edges_test() {
+        let mut new_tree = BTree::new(0, 0, 2);
+        for i in range(1, 50) {
+            new_tree.insert(i * 2, i);
+        }
+        assert_eq!(bound_keys(&new_tree, Included(&10), Included(&10)), ~[10]);
+        assert_eq!(bound_keys(&new_tree, Included(&10), Excluded(&10)), ~[]);
+        assert_eq!(bound_keys(&new_tree, Excluded(&10), Included(&10)), ~[]);
+        assert_eq!(bound_keys(&new_tree, Excluded(&10), Excluded(&12)), ~[]);
+        assert_eq!(bound_keys(&new_tree, Excluded(&10), Included(&12)), ~[12]);
+        assert_eq!(bound_keys(&new_tree, Included(&11), Included(&11)), ~[]);
+        assert_eq!(bound_keys(&new_tree, Included(&20), Included(&10)), ~[]);
+        assert_eq!(bound_keys(&new_tree, Unbounded, Excluded(&4)), ~[0, 2]);
+        assert_eq!(bound_keys(&new_tree, Excluded(&94), Unbounded), ~[96, 98]);
+        let all: ~[int] = range(0, 50).map(|i| i * 2).collect();
+        assert_eq!(bound_keys(&new_tree, Unbounded, Unbounded), all);
+        let empty: BTree<int, int> = BTree::with_degree(2);
+        assert!(empty.range_bounds(Unbounded, Unbounded).next().is_none());
+        assert!(empty.range_bounds(Included(&1), Included(&1)).next().is_none());
+    }
+
+    //The iterator seeks straight to the start of the range, holding one
+    //frame per level instead of walking in from the leftmost leaf.
+    #[test]
+    fn range_pruning_test() {
+        let mut new_tree = BTree::new(0, 0, 2);
+        for i in range(1, 100) {
+            new_tree.insert(i, i);
+        }
+        let mut height = 1;
+        {
+            let mut node = &new_tree.root;
+            while !node.is_leaf() {
+                node = &**node.kids().get(0);
+                height += 1;
+            }
+        }
+        let iter = new_tree.range(&60, &70);
+        assert!(iter.front.len() <= height);
+        for &(node, index) in iter.front.iter() {
+            assert!(index == node.elts.len() || node.elts.get(index).key >= 60);
+        }
+        assert!(iter.back.len() <= height);
+        for &(node, left) in iter.back.iter() {
+            assert!(left == 0 || node.elts.get(left - 1).key < 70);
+        }
+        assert_eq!(iter.remaining, 10);
+    }
+
+    #[test]
+    fn min_max_test() {
+        let mut new_tree = BTree::new(50, ~"50", 2);
+        assert_eq!(new_tree.min(), Some((&50, &~"50")));
+        assert_eq!(new_tree.max(), Some((&50, &~"50")));
+        for i in range(0, 100) {
+            let k = i * 17 % 100;
+            new_tree.insert(k, k.to_str());
+            if k == 0 {
+                assert_eq!(new_tree.min(), Some((&0, &~"0")));
+            }
+        }
+        assert!(!new_tree.root.is_leaf());
+        assert_eq!(new_tree.min(), Some((&0, &~"0")));
+        assert_eq!(new_tree.max(), Some((&99, &~"99")));
+        new_tree.insert(-3, ~"-3");
+        new_tree.insert(150, ~"150");
+        assert_eq!(new_tree.min(), Some((&-3, &~"-3")));
+        assert_eq!(new_tree.max(), Some((&150, &~"150")));
+    }
+
+    #[test]
+    fn peek_min_max_test() {
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.peek_min(), None);
+        assert_eq!(new_tree.peek_max(), None);
+        for i in range(0, 100) {
+            let k = i * 37 % 101;
+            new_tree.insert(k, k * 10);
+            assert_eq!(new_tree.peek_min(), new_tree.min().map(|(_, v)| v));
+            assert_eq!(new_tree.peek_max(), new_tree.max().map(|(_, v)| v));
+        }
+        assert_eq!(new_tree.peek_min(), Some(&0));
+        new_tree.remove(&0);
+        assert_eq!(new_tree.peek_min(), new_tree.min().map(|(_, v)| v));
+        assert_eq!(new_tree.peek_max(), new_tree.max().map(|(_, v)| v));
+    }
+
+    #[test]
+    fn first_last_test() {
+        let mut new_tree = BTree::new(50, 50, 2);
+        assert_eq!(new_tree.first(), Some((&50, &50)));
+        assert_eq!(new_tree.last(), Some((&50, &50)));
+        for i in range(0, 100) {
+            let k = i * 37 % 100;
+            new_tree.find_or_insert(k, k);
+            assert_eq!(new_tree.first(), new_tree.iter().next());
+            assert_eq!(new_tree.last(), new_tree.iter().next_back());
+        }
+        let mut empty_tree = BTree::new(0, 0, 2);
+        empty_tree.remove(&0);
+        assert_eq!(empty_tree.first(), None);
+        assert_eq!(empty_tree.last(), None);
+    }
+
+    #[test]
+    fn clone_test() {
+        let mut new_tree = BTree::new(0, ~"0", 2);
+        for i in range(1, 40) {
+            new_tree.insert(i, i.to_str());
+        }
+        let mut cloned = new_tree.clone();
+        assert_eq!(cloned.len(), 40);
+        cloned.insert(40, ~"40");
+        cloned.insert(0, ~"zero");
+        cloned.remove(&20);
+        assert_eq!(new_tree.len(), 40);
+        assert_eq!(new_tree.find(&0), Some(&~"0"));
+        assert_eq!(new_tree.find(&20), Some(&~"20"));
+        assert!(new_tree.find(&40).is_none());
+        assert_eq!(cloned.find(&0), Some(&~"zero"));
+        assert!(cloned.find(&20).is_none());
+        let keys: ~[int] = new_tree.keys().map(|k| *k).collect();
+        assert_eq!(keys, range(0, 40).collect::<~[int]>());
+    }
+
+    #[test]
+    fn clone_from_test() {
+        let mut source = BTree::with_degree(2);
+        for i in range(0, 60) {
+            source.insert(i * 2, i.to_str());
+        }
+        //A bigger tree, a smaller one, and one of another degree.
+        let mut targets = ~[BTree::with_degree(2), BTree::with_degree(2), BTree::with_degree(4)];
+        for i in range(0, 200) {
+            targets[0].insert(i * 3 + 1, ~"old");
+        }
+        for i in range(0, 5) {
+            targets[1].insert(i * 3 + 1, ~"old");
+        }
+        for i in range(0, 100) {
+            targets[2].insert(i * 3 + 1, ~"old");
+        }
+        for target in targets.mut_iter() {
+            target.clone_from(&source);
+            assert!(*target == source);
+            assert!(target.validate());
+            assert_eq!(target.len(), 60);
+            assert_eq!(target.height(), source.height());
+            assert_eq!(target.min_deg(), 2);
+            assert!(!target.contains_key(&1));
+            assert!(!target.contains_value(&~"old"));
+            target.insert(1, ~"new");
+            assert!(!source.contains_key(&1));
+        }
+    }
+
+    #[test]
+    fn eq_test() {
+        let mut tree_a = BTree::new(0, 0, 2);
+        let mut tree_b = BTree::new(99, 990, 5);
+        for i in range(1, 100) {
+            tree_a.insert(i, i * 10);
+            tree_b.insert(99 - i, (99 - i) * 10);
+        }
+        assert!(tree_a == tree_b);
+        assert!(tree_b == tree_a);
+        tree_b.insert(0, 1);
+        assert!(tree_a != tree_b);
+        tree_b.insert(0, 0);
+        assert!(tree_a == tree_b);
+        tree_b.remove(&50);
+        assert!(tree_a != tree_b);
+        let empty_a: BTree<int, int> = BTree::with_degree(2);
+        let empty_b: BTree<int, int> = BTree::with_degree(3);
+        assert!(empty_a == empty_b);
+    }
+
+    #[test]
+    fn hash_test() {
+        let mut tree_a = BTree::with_degree(2);
+        let mut tree_b = BTree::with_degree(7);
+        for i in range(0, 100) {
+            tree_a.insert(i, i.to_str());
+            tree_b.insert(99 - i, (99 - i).to_str());
+        }
+        assert!(tree_a.height() != tree_b.height());
+        assert_eq!(hash::hash(&tree_a), hash::hash(&tree_b));
+        tree_b.insert(50, ~"fifty");
+        assert!(hash::hash(&tree_a) != hash::hash(&tree_b));
+        let mut trees = HashMap::new();
+        trees.insert(tree_a.clone(), 1);
+        assert_eq!(trees.find(&tree_a), Some(&1));
+        assert!(trees.find(&tree_b).is_none());
+    }
+
+    fn tree_of(pairs: &[(int, int)], md: uint) -> BTree<int, int> {
+        let mut new_tree = BTree::with_degree(md);
+        for &(k, v) in pairs.iter() {
+            new_tree.insert(k, v);
+        }
+        new_tree
+    }
+
+    #[test]
+    fn cmp_prefix_test() {
+        let short = tree_of(&[(1, 1), (2, 2)], 2);
+        let long = tree_of(&[(1, 1), (2, 2), (3, 3)], 3);
+        assert_eq!(short.cmp(&long), Less);
+        assert_eq!(long.cmp(&short), Greater);
+        assert!(short < long);
+        assert!(long > short);
+        let empty = tree_of(&[], 2);
+        assert!(empty < short);
+    }
+
+    #[test]
+    fn cmp_first_difference_test() {
+        let tree_a = tree_of(&[(1, 1), (2, 2), (3, 3)], 2);
+        let tree_b = tree_of(&[(1, 1), (2, 5), (3, 0)], 2);
+        assert_eq!(tree_a.cmp(&tree_b), Less);
+        assert!(tree_a < tree_b);
+        let tree_c = tree_of(&[(1, 1), (4, 0)], 2);
+        assert_eq!(tree_a.cmp(&tree_c), Less);
+        assert!(tree_c >= tree_b);
+    }
+
+    #[test]
+    fn cmp_equal_test() {
+        let pairs = range(0, 50).map(|i| (i, i * 3)).collect::<~[(int, int)]>();
+        let tree_a = tree_of(pairs.as_slice(), 2);
+        let tree_b = tree_of(pairs.as_slice(), 6);
+        assert_eq!(tree_a.cmp(&tree_b), Equal);
+        assert!(tree_a.equals(&tree_b));
+        assert!(tree_a == tree_b);
+        assert!(tree_a <= tree_b && tree_a >= tree_b);
+        assert!(!(tree_a < tree_b));
+        let mut trees = ~[tree_of(&[(2, 0)], 2), tree_a.clone(), tree_of(&[], 2)];
+        trees.sort();
+        assert!(trees[0].is_empty());
+        assert!(trees[1] == tree_a);
+        assert_eq!(trees[2].len(), 1);
+    }
+
+    #[test]
+    fn from_iterator_test() {
+        let mut rng = rand::task_rng();
+        let pairs = rng.shuffle(range(0, 300).map(|i| (i, i.to_str())).collect::<~[(int, ~str)]>());
+        let new_tree: BTree<int, ~str> = pairs.move_iter().collect();
+        assert_eq!(new_tree.len(), 300);
+        let mut expected = 0;
+        for (k, v) in new_tree.iter() {
+            assert_eq!(*k, expected);
+            assert_eq!(*v, expected.to_str());
+            expected += 1;
+        }
+        assert_eq!(expected, 300);
+    }
+
+    #[test]
+    fn extend_test() {
+        let mut new_tree: BTree<int, int> = range(0, 10).map(|i| (i, i)).collect();
+        new_tree.extend(&mut range(5, 20).map(|i| (i, i * 100)));
+        assert_eq!(new_tree.len(), 20);
+        for i in range(0, 5) {
+            assert_eq!(new_tree.find(&i), Some(&i));
+        }
+        for i in range(5, 20) {
+            assert_eq!(new_tree.find(&i), Some(&(i * 100)));
+        }
+    }
+
+    #[test]
+    fn show_test() {
+        let mut new_tree = BTree::new(3, ~"c", 2);
+        new_tree.insert(1, ~"a");
+        new_tree.insert(5, ~"e");
+        new_tree.insert(2, ~"b");
+        new_tree.insert(4, ~"d");
+        assert_eq!(format!("{}", new_tree), ~"{1: a, 2: b, 3: c, 4: d, 5: e}");
+        let empty_tree: BTree<int, int> = BTree::with_degree(2);
+        assert_eq!(format!("{}", empty_tree), ~"{}");
+    }
+
+    #[test]
+    fn insert_returns_old_value_test() {
+        let mut new_tree = BTree::new(1, ~"a", 2);
+        assert_eq!(new_tree.insert(2, ~"b"), None);
+        assert_eq!(new_tree.insert(1, ~"c"), Some(~"a"));
+        assert_eq!(new_tree.insert(1, ~"d"), Some(~"c"));
+        assert_eq!(new_tree.find(&1), Some(&~"d"));
+        assert_eq!(new_tree.len(), 2);
+    }
+
+    #[test]
+    fn dup_policy_overwrite_test() {
+        let mut new_tree = BTree::with_dup_policy(2, Overwrite);
+        assert_eq!(new_tree.dup_policy(), Overwrite);
+        for i in range(0, 50) {
+            new_tree.insert(i, i.to_str());
+        }
+        //Keys in leaves and keys that are separators in branches.
+        for i in range(0, 50) {
+            assert_eq!(new_tree.insert(i, ~"new"), Some(i.to_str()));
+        }
+        assert!(new_tree.values().all(|v| *v == ~"new"));
+        assert_eq!(new_tree.len(), 50);
+    }
+
+    #[test]
+    fn dup_policy_keep_first_test() {
+        let mut new_tree = BTree::with_dup_policy(2, KeepFirst);
+        assert_eq!(new_tree.dup_policy(), KeepFirst);
+        for i in range(0, 50) {
+            assert_eq!(new_tree.insert(i, i.to_str()), None);
+        }
+        for i in range(0, 50) {
+            assert_eq!(new_tree.insert(i, ~"new"), Some(~"new"));
+        }
+        for (k, v) in new_tree.iter() {
+            assert_eq!(*v, k.to_str());
+        }
+        assert_eq!(new_tree.len(), 50);
+        assert!(new_tree.validate());
+        //The policy survives operations that rebuild the tree.
+        new_tree.retain(|k, _| *k % 2 == 0);
+        assert_eq!(new_tree.insert(10, ~"ten"), Some(~"ten"));
+        assert_eq!(new_tree.clone().dup_policy(), KeepFirst);
+        assert_eq!(new_tree.split_off(&20).dup_policy(), KeepFirst);
+        assert_eq!(new_tree.dup_policy(), KeepFirst);
+    }
+
+    #[test]
+    fn entry_occupied_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 30) {
+            new_tree.insert(i, i);
+        }
+        match new_tree.entry(12) {
+            Occupied(mut entry) => {
+                assert_eq!(*entry.get(), 12);
+                *entry.get_mut() += 100;
+                assert_eq!(entry.insert(7), 112);
+            }
+            Vacant(_) => fail!("12 is in the tree")
+        }
+        assert_eq!(new_tree.find(&12), Some(&7));
+        *new_tree.entry(20).or_insert(0) += 1;
+        assert_eq!(new_tree.find(&20), Some(&21));
+        assert_eq!(*new_tree.entry(5).or_insert_with(|| fail!("5 is in the tree")), 5);
+        new_tree.entry(3).and_modify(|v| *v *= 10).or_insert(0);
+        assert_eq!(new_tree.find(&3), Some(&30));
+        assert_eq!(new_tree.len(), 30);
+        assert!(new_tree.validate());
+    }
+
+    #[test]
+    fn entry_vacant_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for w in ["a", "b", "a", "c", "a", "b"].iter() {
+            *new_tree.entry(w.to_owned()).or_insert(0) += 1;
+        }
+        let counts: ~[(~str, int)] = new_tree.clone().move_iter().collect();
+        assert_eq!(counts, ~[(~"a", 3), (~"b", 2), (~"c", 1)]);
+        match new_tree.entry(~"d") {
+            Occupied(_) => fail!("d is not in the tree"),
+            Vacant(entry) => {
+                *entry.insert(4) += 1;
+            }
+        }
+        assert_eq!(new_tree.find(&~"d"), Some(&5));
+        assert_eq!(*new_tree.entry(~"e").or_insert_with(|| 9), 9);
+        new_tree.entry(~"f").and_modify(|v| *v += 1).or_insert(1);
+        assert_eq!(new_tree.find(&~"f"), Some(&1));
+        assert_eq!(new_tree.len(), 6);
+        let mut big_tree = BTree::with_degree(2);
+        for i in range(0, 200) {
+            *big_tree.entry(i % 50).or_insert_with(|| 0) += 1;
+        }
+        assert_eq!(big_tree.len(), 50);
+        assert!(big_tree.values().all(|v| *v == 4));
+        assert!(big_tree.validate());
+        //A vacant entry that is dropped may have split nodes on the way
+        //down, but leaves the tree valid and its contents unchanged.
+        for i in range(50, 100) {
+            match big_tree.entry(i) {
+                Occupied(_) => fail!("{} is not in the tree", i),
+                Vacant(_) => {}
+            }
+        }
+        assert_eq!(big_tree.len(), 50);
+        assert!(big_tree.validate());
+    }
+
+    #[test]
+    fn swap_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 20) {
+            assert_eq!(new_tree.swap(i, i.to_str()), None);
+        }
+        assert_eq!(new_tree.swap(7, ~"seven"), Some(~"7"));
+        assert_eq!(new_tree.swap(7, ~"sept"), Some(~"seven"));
+        assert_eq!(new_tree.find(&7), Some(&~"sept"));
+        assert_eq!(new_tree.len(), 20);
+        assert!(new_tree.validate());
+    }
+
+    #[test]
+    fn swap_keep_first_test() {
+        let mut new_tree = BTree::with_dup_policy(2, KeepFirst);
+        for i in range(0, 20) {
+            assert_eq!(new_tree.swap(i, i.to_str()), None);
+        }
+        //swap keeps its contract under KeepFirst: the new value goes in
+        //and the old one comes back.
+        assert_eq!(new_tree.swap(7, ~"seven"), Some(~"7"));
+        assert_eq!(new_tree.find(&7), Some(&~"seven"));
+        assert_eq!(new_tree.insert(7, ~"sept"), Some(~"sept"));
+        assert_eq!(new_tree.find(&7), Some(&~"seven"));
+        assert_eq!(new_tree.len(), 20);
+        assert!(new_tree.validate());
+    }
+
+    #[test]
+    fn pop_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 20) {
+            new_tree.insert(i, i.to_str());
+        }
+        assert_eq!(new_tree.pop(&7), Some(~"7"));
+        assert_eq!(new_tree.pop(&7), None);
+        assert_eq!(new_tree.pop(&20), None);
+        assert_eq!(new_tree.len(), 19);
+        assert!(!new_tree.contains_key(&7));
+        assert!(new_tree.validate());
+    }
+
+    fn exercise_map<M: MutableMap<int, int>>(map: &mut M) {
+        for i in range(0, 30) {
+            assert!(map.insert(i, i * 10));
+        }
+        assert_eq!(map.len(), 31);
+        assert!(!map.insert(29, 290));
+        assert_eq!(map.swap(-1, -11), Some(-10));
+        assert_eq!(map.find(&29), Some(&290));
+        assert!(map.contains_key(&29));
+        match map.find_mut(&7) {
+            None => fail!(),
+            Some(v) => *v = 77
+        }
+        assert_eq!(map.find(&7), Some(&77));
+        assert_eq!(map.pop(&8), Some(80));
+        assert!(map.remove(&9));
+        assert!(!map.remove(&9));
+        assert_eq!(map.len(), 29);
+        map.clear();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn with_degree_test() {
+        let mut new_tree: BTree<int, ~str> = BTree::with_degree(2);
+        assert!(new_tree.is_empty());
+        assert!(new_tree.find(&1).is_none());
+        assert!(new_tree.remove(&1).is_none());
+        assert!(new_tree.iter().next().is_none());
+        assert!(new_tree.min().is_none());
+        assert_eq!(new_tree.insert(1, ~"a"), None);
+        assert_eq!(new_tree.find(&1), Some(&~"a"));
+        assert_eq!(new_tree.len(), 1);
+        for i in range(2, 30) {
+            new_tree.insert(i, i.to_str());
+        }
+        assert_eq!(new_tree.len(), 29);
+        assert_eq!(new_tree.find(&17), Some(&~"17"));
+    }
+
+    #[test]
+    fn max_elts_test() {
+        let small_tree: BTree<int, ()> = BTree::with_degree(2);
+        assert_eq!(small_tree.max_elts(), 3);
+        assert_eq!(small_tree.min_elts(), 1);
+        let md = uint::MAX / 2;
+        let mut new_tree = BTree::with_degree(md);
+        assert_eq!(new_tree.max_elts(), uint::MAX - 2);
+        assert_eq!(new_tree.min_elts(), md - 1);
+        for i in range(0, 100) {
+            new_tree.insert(i, ());
+        }
+        assert_eq!(new_tree.height(), 1);
+        assert!(new_tree.validate());
+    }
+
+    #[test]
+    #[should_fail]
+    fn max_elts_overflow_test() {
+        let _: BTree<int, ()> = BTree::with_degree(uint::MAX / 2 + 1);
+    }
+
+    #[test]
+    fn next_level_cap_test() {
+        assert_eq!(next_level_cap_for(3, 2), 15);
+        assert_eq!(next_level_cap_for(15, 2), 63);
+        assert_eq!(next_level_cap_for(5, 3), 35);
+        assert_eq!(next_level_cap_for(0, uint::MAX / 2), uint::MAX - 1);
+    }
+
+    #[test]
+    #[should_fail]
+    fn next_level_cap_overflow_test() {
+        next_level_cap_for(uint::MAX - 2, uint::MAX / 2);
+    }
+
+    #[test]
+    fn min_deg_test() {
+        assert_eq!(BTree::new(1, (), 2).min_deg(), 2);
+        for md in range(2u, 10) {
+            let mut new_tree = BTree::with_degree(md);
+            assert_eq!(new_tree.min_deg(), md);
+            for i in range(0, 100) {
+                new_tree.insert(i, ());
+            }
+            assert_eq!(new_tree.min_deg(), md);
+            assert_eq!(BTree::from_sorted(~[(1, ())], md).min_deg(), md);
+            let keep_first: BTree<int, ()> = BTree::with_dup_policy(md, KeepFirst);
+            assert_eq!(keep_first.min_deg(), md);
+        }
+        let default_tree: BTree<int, ()> = Default::default();
+        assert_eq!(default_tree.min_deg(), 6);
+    }
+
+    #[test]
+    #[should_fail]
+    fn min_deg_1_test() {
+        BTree::new(1, ~"a", 1);
+    }
+
+    #[test]
+    #[should_fail]
+    fn with_degree_1_test() {
+        let _new_tree: BTree<int, ~str> = BTree::with_degree(1);
+    }
+
+    #[test]
+    fn optimal_degree_for_test() {
+        let elt_size = size_of::<Elt<int, int>>();
+        for budget in [64u, 128, 4096, 4096 + elt_size - 1].iter() {
+            let md = optimal_degree_for::<int, int>(*budget);
+            //A full node fits the budget, and one more pair each side would not.
+            assert!((2 * md - 1) * elt_size <= *budget);
+            assert!((2 * md + 1) * elt_size > *budget);
+        }
+        assert_eq!(optimal_degree_for::<int, int>(0), 2);
+        assert_eq!(optimal_degree_for::<int, int>(elt_size * 3), 2);
+        assert_eq!(optimal_degree_for::<int, int>(elt_size * 5), 3);
+        //A budget of every byte there is, for pairs of one byte each.
+        assert_eq!(size_of::<Elt<u8, ()>>(), 1);
+        assert_eq!(optimal_degree_for::<u8, ()>(uint::MAX), uint::MAX / 2);
+        assert_eq!(optimal_degree_for::<u8, ()>(uint::MAX - 1), uint::MAX / 2);
+        assert_eq!(optimal_degree_for::<(), ()>(uint::MAX), 6);
+        assert_eq!(optimal_degree_for::<(), ()>(0), 6);
+        let new_tree: BTree<int, int> = BTree::with_node_budget(4096);
+        assert_eq!(new_tree.min_deg(), optimal_degree_for::<int, int>(4096));
+    }
+
+    #[test]
+    fn min_deg_2_test() {
+        let mut new_tree = BTree::new(1, ~"a", 2);
+        new_tree.insert(2, ~"b");
+        assert_eq!(new_tree.len(), 2);
+        let empty_tree: BTree<int, ~str> = BTree::with_degree(2);
+        assert!(empty_tree.is_empty());
+    }
+
+    #[test]
+    fn default_test() {
+        let mut new_tree: BTree<int, ~str> = Default::default();
+        assert!(new_tree.is_empty());
+        assert_eq!(new_tree.min_deg, 6);
+        for i in range(0, 50) {
+            new_tree.insert(i, i.to_str());
+        }
+        assert_eq!(new_tree.len(), 50);
+        assert_eq!(new_tree.find(&25), Some(&~"25"));
+        assert!(new_tree.validate());
+    }
+
+    //Inserting 1 to 10 in order into a tree of minimum degree 2 leaves 4
+    //at the root and 2, 6 and 8 as separators below it.
+    #[test]
+    fn insert_separator_update_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(1, 11) {
+            new_tree.insert(i, i);
+        }
+        assert_eq!(new_tree.root.elts.get(0).key, 4);
+        for &k in [4, 2, 6, 8].iter() {
+            assert_eq!(new_tree.insert(k, -k), Some(k));
+        }
+        assert_eq!(new_tree.len(), 10);
+        assert!(new_tree.validate());
+        let keys: ~[int] = new_tree.keys().map(|k| *k).collect();
+        assert_eq!(keys, range(1, 11).collect::<~[int]>());
+        for &k in [4, 2, 6, 8].iter() {
+            assert_eq!(new_tree.find(&k), Some(&-k));
+        }
+    }
+
+    //The median a full child sends up on the way down can be the key being
+    //inserted.
+    #[test]
+    fn insert_split_median_update_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(1, 4) {
+            new_tree.insert(i, i);
+        }
+        //The root is full, and splits around 2 before 2 is looked for.
+        assert_eq!(new_tree.insert(2, 20), Some(2));
+        new_tree.insert(4, 4);
+        new_tree.insert(5, 5);
+        //The right leaf is now full, and splits around 4.
+        assert_eq!(new_tree.insert(4, 40), Some(4));
+        assert_eq!(new_tree.len(), 5);
+        assert!(new_tree.validate());
+        assert_eq!(new_tree.find(&2), Some(&20));
+        assert_eq!(new_tree.find(&4), Some(&40));
+    }
+
+    #[test]
+    fn insert_after_clear_test() {
+        let mut new_tree = BTree::new(0, 0, 2);
+        for i in range(1, 30) {
+            new_tree.insert(i, i);
+        }
+        new_tree.clear();
+        assert!(new_tree.find(&5).is_none());
+        new_tree.insert(5, 50);
+        assert_eq!(new_tree.find(&5), Some(&50));
+        assert_eq!(new_tree.len(), 1);
+    }
+
+    //Clearing drops the whole structure, not just the entries.
+    #[test]
+    fn clear_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 100) {
+            new_tree.insert(i, i);
+        }
+        assert!(new_tree.height() > 1);
+        new_tree.clear();
+        assert_eq!(new_tree.len(), 0);
+        assert!(new_tree.is_empty());
+        assert_eq!(new_tree.height(), 1);
+        assert_eq!(new_tree.node_count(), 1);
+        assert!(new_tree.validate());
+        for i in range(0, 100) {
+            new_tree.insert(i, -i);
+        }
+        assert_eq!(new_tree.len(), 100);
+        assert!(new_tree.validate());
+        assert_eq!(new_tree.find(&99), Some(&-99));
+    }
+
+    #[test]
+    fn mutable_map_test() {
+        let mut new_tree = BTree::new(-1, -10, 2);
+        exercise_map(&mut new_tree);
+    }
+
+    #[test]
+    fn find_test_1() {
+        let new_tree = BTree::new(1, ~"a", 2);
+        assert_eq!(new_tree.find(&1), Some(&~"a"));
+        assert_eq!(new_tree.find(&2), None);
+    }
+
+    //Key stored in an internal node.
+    #[test]
+    fn find_test_2() {
+        let new_node = Node { elts: vec!(Elt { key: 3, value: ~"c" }),
+                              kind: Branch(vec!(~Node { elts: vec!(Elt { key: 1, value: ~"a" },
+                                                               Elt { key: 2, value: ~"b" }),
+                                                       kind: Leaf },
+                                               ~Node { elts: vec!(Elt { key: 4, value: ~"d" },
+                                                               Elt { key: 5, value: ~"e" }),
+                                                       kind: Leaf }), 5)};
+        let new_tree = BTree { root: new_node, len: 5, height: 2, min_deg: 2,
+                               dup_policy: Overwrite };
+        assert_eq!(new_tree.find(&3), Some(&~"c"));
+    }
+
+    //Keys stored in leaves, and keys that fall between leaves.
+    #[test]
+    fn find_test_3() {
+        let new_node = Node { elts: vec!(Elt { key: 3, value: ~"c" }),
+                              kind: Branch(vec!(~Node { elts: vec!(Elt { key: 1, value: ~"a" },
+                                                               Elt { key: 2, value: ~"b" }),
+                                                       kind: Leaf },
+                                               ~Node { elts: vec!(Elt { key: 5, value: ~"e" },
+                                                               Elt { key: 7, value: ~"g" }),
+                                                       kind: Leaf }), 5)};
+        let new_tree = BTree { root: new_node, len: 5, height: 2, min_deg: 2,
+                               dup_policy: Overwrite };
+        assert_eq!(new_tree.find(&1), Some(&~"a"));
+        assert_eq!(new_tree.find(&7), Some(&~"g"));
+        assert_eq!(new_tree.find(&0), None);
+        assert_eq!(new_tree.find(&4), None);
+        assert_eq!(new_tree.find(&8), None);
+    }
+
+    //Every key inserted into a tree of several levels can be found again.
+    #[test]
+    fn find_test_4() {
+        let mut new_tree = BTree::new(0, 0, 2);
+        for i in range(1, 100) {
+            new_tree.insert(i * 7 % 100, i);
+        }
+        assert!(!new_tree.root.is_leaf());
+        for i in range(1, 100) {
+            assert_eq!(new_tree.find(&(i * 7 % 100)), Some(&i));
+        }
+        assert_eq!(new_tree.find(&100), None);
+    }
+
+    #[test]
+    fn find_iterative_test() {
+        let mut rng = rand::task_rng();
+        for md in range(2u, 5) {
+            let mut new_tree = BTree::with_degree(md);
+            for _ in range(0, 1000) {
+                let k = rng.gen_range(0, 2000);
+                new_tree.insert(k, k * 3);
+            }
+            for k in range(-1, 2001) {
+                assert_eq!(new_tree.find(&k), find_recursive(&new_tree.root, &k));
+            }
+        }
+    }
+
+    #[test]
+    fn find_mut_test() {
+        let mut new_tree = BTree::new(0, ~"0", 2);
+        for i in range(1, 20) {
+            new_tree.insert(i, i.to_str());
+        }
+        match new_tree.find_mut(&13) {
+            None => fail!(),
+            Some(v) => *v = ~"thirteen"
+        }
+        assert_eq!(new_tree.find(&13), Some(&~"thirteen"));
+        assert_eq!(new_tree.find(&12), Some(&~"12"));
+        assert!(new_tree.find_mut(&20).is_none());
+    }
+
+    #[test]
+    fn find_with_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 40) {
+            new_tree.insert((i * 3, 100 - i), i.to_str());
+        }
+        for i in range(0, 40) {
+            let found = new_tree.find_with(|&(a, _)| a.cmp(&(i * 3)));
+            assert_eq!(found, Some(&i.to_str()));
+            assert_eq!(new_tree.find_with(|&(a, _)| a.cmp(&(i * 3 + 1))), None);
+        }
+        assert_eq!(new_tree.find_with(|&(a, _)| a.cmp(&-1)), None);
+        assert_eq!(new_tree.find_with(|&(a, _)| a.cmp(&120)), None);
+    }
+
+    //Keys that are ordered by id alone, whatever their tags.
+    struct Tagged {
+        id: int,
+        tag: &'static str
+    }
+
+    impl TotalEq for Tagged {
+        fn equals(&self, other: &Tagged) -> bool {
+            self.id == other.id
+        }
+    }
+
+    impl TotalOrd for Tagged {
+        fn cmp(&self, other: &Tagged) -> Ordering {
+            self.id.cmp(&other.id)
+        }
+    }
+
+    #[test]
+    fn get_key_value_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 30) {
+            new_tree.insert(Tagged { id: i, tag: "stored" }, i);
+        }
+        match new_tree.get_key_value(&Tagged { id: 17, tag: "query" }) {
+            None => fail!(),
+            Some((k, v)) => {
+                assert_eq!(k.id, 17);
+                assert_eq!(k.tag, "stored");
+                assert_eq!(*v, 17);
+            }
+        }
+        assert!(new_tree.get_key_value(&Tagged { id: 30, tag: "query" }).is_none());
+    }
+
+    #[test]
+    fn get_or_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 20) {
+            new_tree.insert(i * 2, i.to_str());
+        }
+        let default = ~"none";
+        assert_eq!(new_tree.get_or(&10, &default), &~"5");
+        assert_eq!(new_tree.get_or(&11, &default), &~"none");
+        assert_eq!(new_tree.get_or(&-1, &default), &~"none");
+        assert_eq!(new_tree.len(), 20);
+    }
+
+    #[test]
+    fn find_pair_mut_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 30) {
+            new_tree.insert(i, i.to_str());
+        }
+        match new_tree.find_pair_mut(&3, &27) {
+            None => fail!(),
+            Some((a, b)) => swap(a, b)
+        }
+        assert_eq!(new_tree.find(&3), Some(&~"27"));
+        assert_eq!(new_tree.find(&27), Some(&~"3"));
+        assert!(new_tree.find_pair_mut(&3, &30).is_none());
+        assert!(new_tree.find_pair_mut(&-1, &3).is_none());
+    }
+
+    #[test]
+    fn find_pair_mut_same_key_test() {
+        let mut new_tree = BTree::new(1, 1, 2);
+        new_tree.insert(2, 2);
+        assert!(new_tree.find_pair_mut(&1, &1).is_none());
+        assert!(new_tree.find_pair_mut(&5, &5).is_none());
+    }
+
+    #[test]
+    fn contains_value_test() {
+        let mut new_tree = BTree::with_degree(2);
+        assert!(!new_tree.contains_value(&~"a"));
+        for i in range(0, 50) {
+            new_tree.insert(i, i.to_str());
+        }
+        assert!(new_tree.contains_value(&~"0"));
+        assert!(new_tree.contains_value(&~"27"));
+        assert!(new_tree.contains_value(&~"49"));
+        assert!(!new_tree.contains_value(&~"50"));
+        new_tree.remove(&27);
+        assert!(!new_tree.contains_value(&~"27"));
+    }
+
+    #[test]
+    fn find_borrowed_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 50) {
+            new_tree.insert(i.to_str(), i);
+        }
+        assert_eq!(new_tree.find_borrowed(&"0"), Some(&0));
+        assert_eq!(new_tree.find_borrowed(&"17"), Some(&17));
+        assert_eq!(new_tree.find_borrowed(&"49"), Some(&49));
+        assert_eq!(new_tree.find_borrowed(&"50"), None);
+        assert!(new_tree.contains_key_borrowed(&"3"));
+        assert!(!new_tree.contains_key_borrowed(&""));
+        let name = ~"prefix-23";
+        assert_eq!(new_tree.find_borrowed(&name.slice_from(7)), Some(&23));
+        assert_eq!("23".compare_key(&~"3"), Greater);
+
+        let mut vec_tree = BTree::with_degree(3);
+        for i in range(0, 20) {
+            vec_tree.insert(~[i, i + 1], i);
+        }
+        let probe = [4, 5];
+        assert_eq!(vec_tree.find_borrowed(&probe.as_slice()), Some(&4));
+        assert_eq!(vec_tree.find_borrowed(&[4, 6].as_slice()), None);
+    }
+
+    #[test]
+    fn remove_borrowed_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 50) {
+            new_tree.insert(i.to_str(), i);
+        }
+        assert_eq!(new_tree.remove_borrowed(&"17"), Some(17));
+        assert_eq!(new_tree.remove_borrowed(&"17"), None);
+        assert_eq!(new_tree.remove_borrowed(&"50"), None);
+        let name = ~"prefix-23";
+        assert_eq!(new_tree.remove_borrowed(&name.slice_from(7)), Some(23));
+        for i in range(0, 50) {
+            if i != 17 && i != 23 {
+                assert_eq!(new_tree.remove_borrowed(&i.to_str().as_slice()), Some(i));
+                assert!(new_tree.validate());
+            }
+        }
+        assert!(new_tree.is_empty());
+        assert_eq!(new_tree.remove_borrowed(&"0"), None);
+    }
+
+    #[test]
+    fn contains_key_test() {
+        let mut new_tree = BTree::new(0, (), 2);
+        for i in range(1, 50) {
+            new_tree.insert(i * 2, ());
+        }
+        let root_key = new_tree.root.elts.get(0).key;
+        let internal_key;
+        let leaf_key;
+        {
+            let internal = new_tree.root.kids().get(0);
+            assert!(!internal.is_leaf());
+            internal_key = internal.elts.get(0).key;
+            let mut node = internal;
+            while !node.is_leaf() {
+                node = node.kids().get(0);
+            }
+            leaf_key = node.elts.get(0).key;
+        }
+        assert!(new_tree.contains_key(&root_key));
+        assert!(new_tree.contains_key(&internal_key));
+        assert!(new_tree.contains_key(&leaf_key));
+        assert!(!new_tree.contains_key(&-1));
+        assert!(!new_tree.contains_key(&7));
+        assert!(!new_tree.contains_key(&100));
+    }
+
+    #[test]
+    fn from_sorted_test() {
+        let pairs = range(0, 1000).map(|i| (i, i.to_str())).collect::<~[(int, ~str)]>();
+        let new_tree = BTree::from_sorted(pairs.clone(), 3);
+        assert_eq!(new_tree.len(), 1000);
+        assert!(new_tree.validate());
+        let entries: ~[(int, ~str)] = new_tree.iter().map(|(k, v)| (*k, v.clone())).collect();
+        assert_eq!(entries, pairs);
+        //1000 elements need at least 200 nodes of 5; stay within 10% of that.
+        assert!(new_tree.node_count() <= 220);
+    }
+
+    #[test]
+    fn from_sorted_small_test() {
+        for n in range(0, 60) {
+            for md in range(2u, 5) {
+                let pairs = range(0, n).map(|i| (i, i)).collect::<~[(int, int)]>();
+                let new_tree = BTree::from_sorted(pairs, md);
+                assert_eq!(new_tree.len(), n as uint);
+                assert!(new_tree.validate());
+                let keys: ~[int] = new_tree.keys().map(|k| *k).collect();
+                assert_eq!(keys, range(0, n).collect::<~[int]>());
+            }
+        }
+    }
+
+    //The ordering check only runs in debug builds.
+    #[test]
+    #[should_fail]
+    #[cfg(not(ndebug))]
+    fn from_sorted_unsorted_test() {
+        BTree::from_sorted(~[(1, ()), (3, ()), (2, ())], 2);
+    }
+
+    #[test]
+    fn try_from_sorted_test() {
+        let pairs = range(0, 100).map(|i| (i, i)).collect::<~[(int, int)]>();
+        let new_tree = BTree::try_from_sorted(pairs, 2).unwrap();
+        assert_eq!(new_tree.len(), 100);
+        assert!(new_tree.validate());
+        let empty: Result<BTree<int, ()>, ~str> = BTree::try_from_sorted(~[], 2);
+        assert!(empty.unwrap().is_empty());
+
+        let dup = BTree::try_from_sorted(~[(1, ()), (2, ()), (2, ()), (3, ())], 2);
+        assert_eq!(dup.unwrap_err(), ~"BTree::try_from_sorted: the keys at 1 and 2 are equal");
+        let desc = BTree::try_from_sorted(~[(1, ()), (5, ()), (4, ()), (3, ())], 2);
+        assert_eq!(desc.unwrap_err(),
+                   ~"BTree::try_from_sorted: the key at 2 is less than the key at 1");
+    }
+
+    #[test]
+    fn from_sorted_dedup_test() {
+        let pairs = ~[(1, 'a'), (1, 'b'), (2, 'c'), (3, 'd'), (3, 'e'), (3, 'f'), (4, 'g')];
+        let new_tree = BTree::from_sorted_dedup(pairs, 2);
+        assert!(new_tree.validate());
+        let entries: ~[(int, char)] = new_tree.iter().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(entries, ~[(1, 'b'), (2, 'c'), (3, 'f'), (4, 'g')]);
+
+        let pairs = range(0, 300).map(|i| (i / 3, i)).collect::<~[(int, int)]>();
+        let new_tree = BTree::from_sorted_dedup(pairs, 3);
+        assert_eq!(new_tree.len(), 100);
+        assert!(new_tree.validate());
+        assert!(new_tree.iter().all(|(k, v)| *v == *k * 3 + 2));
+        assert!(BTree::<int, ()>::from_sorted_dedup(~[], 2).is_empty());
+    }
+
+    #[test]
+    fn from_sorted_iter_test() {
+        let new_tree = BTree::from_sorted_iter(range(0, 100000).map(|i| (i, i * 2)), 3);
+        assert_eq!(new_tree.len(), 100000);
+        assert!(new_tree.validate());
+        assert!(new_tree.iter().enumerate().all(|(i, (k, v))| *k == i as int && *v == *k * 2));
+        assert_eq!(new_tree.select(54321), Some((&54321, &108642)));
+        //Only the right edge may be less than full.
+        let full_nodes = (100000 + 4) / 5;
+        assert!(new_tree.node_count() <= full_nodes + new_tree.height());
+    }
+
+    #[test]
+    fn from_sorted_iter_small_test() {
+        for n in range(0, 200) {
+            for md in range(2u, 5) {
+                let new_tree = BTree::from_sorted_iter(range(0, n).map(|i| (i, ())), md);
+                assert_eq!(new_tree.len(), n as uint);
+                assert!(new_tree.validate());
+                let keys: ~[int] = new_tree.keys().map(|k| *k).collect();
+                assert_eq!(keys, range(0, n).collect::<~[int]>());
+            }
+        }
+    }
+
+    //The ordering check only runs in debug builds.
+    #[test]
+    #[should_fail]
+    #[cfg(not(ndebug))]
+    fn from_sorted_iter_unsorted_test() {
+        BTree::from_sorted_iter(range(0, 10).map(|i| (i % 7, ())), 2);
+    }
+
+    #[test]
+    fn height_test() {
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.height(), 1);
+        let mut height = 1;
+        for i in range(0, 200) {
+            //The root is about to split exactly when it is full.
+            let root_full = new_tree.root.elts.len() == 3;
+            new_tree.insert(i, ());
+            if root_full {
+                height += 1;
+            }
+            assert_eq!(new_tree.height(), height);
+        }
+        assert!(height > 3);
+        //As the root collapses, the cached height must keep matching the
+        //length of the path down to the leaves.
+        let mut rng = rand::task_rng();
+        for k in rng.shuffle(range(0, 200).collect::<~[int]>()).iter() {
+            new_tree.remove(k);
+            let mut depth = 1;
+            let mut node = &new_tree.root;
+            while !node.is_leaf() {
+                node = &**node.kids().get(0);
+                depth += 1;
+            }
+            assert_eq!(new_tree.height(), depth);
+        }
+        assert_eq!(new_tree.height(), 1);
+    }
+
+    //Returns the spare capacity of the vectors in a subtree.
+    fn spare_capacity(node: &Node<int, int>) -> uint {
+        let spare = node.elts.capacity() - node.elts.len();
+        match node.kind {
+            Leaf => spare,
+            Branch(ref kids, _) => {
+                kids.iter().fold(spare + kids.capacity() - kids.len(),
+                                 |n, kid| n + spare_capacity(&**kid))
+            }
+        }
+    }
+
+    #[test]
+    fn reserve_test() {
+        let mut new_tree = BTree::with_degree(20);
+        new_tree.reserve(30);
+        let capacity = new_tree.root.elts.capacity();
+        assert!(capacity >= 30);
+        let ptr = new_tree.root.elts.as_slice().as_ptr();
+        for i in range(0, 30) {
+            new_tree.insert(i, i);
+            assert_eq!(new_tree.root.elts.capacity(), capacity);
+            assert_eq!(new_tree.root.elts.as_slice().as_ptr(), ptr);
+        }
+        //Reserving never asks for more than a full node.
+        let mut new_tree: BTree<int, int> = BTree::with_degree(2);
+        new_tree.reserve(1000);
+        assert_eq!(new_tree.root.elts.capacity(), 3);
+        new_tree.reserve(1);
+        assert_eq!(new_tree.root.elts.capacity(), 3);
+    }
+
+    #[test]
+    fn capacity_test() {
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.capacity(), 0);
+        new_tree.reserve(3);
+        assert_eq!(new_tree.capacity(), 3);
+        for i in range(0, 300) {
+            new_tree.insert(i * 7 % 300, i);
+            assert!(new_tree.capacity() >= new_tree.len());
+        }
+        assert!(new_tree.capacity() >= 300);
+        for i in range(0, 300) {
+            if i % 3 != 0 {
+                new_tree.remove(&i);
+            }
+            assert!(new_tree.capacity() >= new_tree.len());
+        }
+        new_tree.shrink_to_fit();
+        assert_eq!(new_tree.capacity(), new_tree.len());
+    }
+
+    #[test]
+    fn shrink_to_fit_test() {
+        let mut new_tree = BTree::with_degree(4);
+        for i in range(0, 500) {
+            new_tree.insert(i, i);
+        }
+        for i in range(0, 500) {
+            if i % 5 != 0 {
+                new_tree.remove(&i);
+            }
+        }
+        assert!(spare_capacity(&new_tree.root) > 0);
+        new_tree.shrink_to_fit();
+        assert_eq!(spare_capacity(&new_tree.root), 0);
+        assert!(new_tree.validate());
+        assert_eq!(new_tree.len(), 100);
+        new_tree.insert(1, 1);
+        assert_eq!(new_tree.find(&1), Some(&1));
+    }
+
+    #[test]
+    fn node_count_test() {
+        let new_tree: BTree<int, ()> = BTree::with_degree(2);
+        assert_eq!(new_tree.node_count(), 1);
+        let new_node = Node { elts: vec!(Elt { key: 3, value: () }, Elt { key: 7, value: () }),
+                              kind: Branch(vec!(~Node { elts: vec!(Elt { key: 1, value: () }),
+                                                       kind: Leaf },
+                                               ~Node { elts: vec!(Elt { key: 5, value: () }),
+                                                       kind: Leaf },
+                                               ~Node { elts: vec!(Elt { key: 9, value: () }),
+                                                       kind: Leaf }), 5)};
+        let new_tree = BTree { root: new_node, len: 5, height: 2, min_deg: 2,
+                               dup_policy: Overwrite };
+        assert_eq!(new_tree.node_count(), 4);
+        assert_eq!(new_tree.height(), 2);
+    }
+
+    //Inserting 1 to 10 in order into a tree of minimum degree 2 splits the
+    //root twice, leaving three levels.
+    #[test]
+    fn levels_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(1, 11) {
+            new_tree.insert(i, ());
+        }
+        let levels: ~[~[int]] = new_tree.levels().map(|keys| keys.map(|k| **k)).collect();
+        assert_eq!(levels, ~[~[4], ~[2], ~[6, 8], ~[1], ~[3], ~[5], ~[7], ~[9, 10]]);
+        let empty_tree: BTree<int, ()> = BTree::with_degree(2);
+        let levels: ~[~[&int]] = empty_tree.levels().collect();
+        assert_eq!(levels, ~[~[]]);
+    }
+
+    #[test]
+    fn get_path_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(1, 11) {
+            new_tree.insert(i, ());
+        }
+        let path = |k: int| -> ~[~[int]] {
+            new_tree.get_path(&k).move_iter().map(|keys| keys.map(|k| **k)).collect()
+        };
+        assert_eq!(path(9), ~[~[4], ~[6, 8], ~[9, 10]]);
+        assert_eq!(path(1), ~[~[4], ~[2], ~[1]]);
+        assert_eq!(path(6), ~[~[4], ~[6, 8]]);
+        assert_eq!(path(4), ~[~[4]]);
+        assert_eq!(path(7), ~[~[4], ~[6, 8], ~[7]]);
+        assert_eq!(path(11), ~[~[4], ~[6, 8], ~[9, 10]]);
+        let empty_tree: BTree<int, ()> = BTree::with_degree(2);
+        assert_eq!(empty_tree.get_path(&1), ~[~[]]);
+    }
+
+    #[test]
+    fn to_dot_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(1, 11) {
+            new_tree.insert(i, ());
+        }
+        let dot = new_tree.to_dot();
+        assert!(dot.starts_with("digraph btree {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("n0 [label=\"4\"];"));
+        assert!(dot.contains("n2 [label=\"6|8\"];"));
+        assert!(dot.contains("n7 [label=\"9|10\"];"));
+        assert!(dot.contains("n2 -> n7;"));
+        assert_eq!(dot.lines().count(|line| line.contains("[label=")), 8);
+        assert_eq!(dot.lines().count(|line| line.contains("->")), 7);
+        assert_eq!(dot, new_tree.clone().to_dot());
+    }
+
+    #[test]
+    fn level_stats_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(1, 11) {
+            new_tree.insert(i, ());
+        }
+        assert_eq!(new_tree.level_stats(), ~[1, 3, 6]);
+        let mut rng = rand::task_rng();
+        for md in range(2u, 6) {
+            let mut new_tree = BTree::with_degree(md);
+            for _ in range(0, 500) {
+                let k = rng.gen_range(0, 5000);
+                if !new_tree.contains_key(&k) {
+                    new_tree.insert(k, ());
+                }
+            }
+            let stats = new_tree.level_stats();
+            assert_eq!(stats.len(), new_tree.height());
+            assert_eq!(stats.iter().fold(0, |n, &count| n + count), new_tree.len());
+        }
+        let empty_tree: BTree<int, ()> = BTree::with_degree(2);
+        assert_eq!(empty_tree.level_stats(), ~[0]);
+    }
+
+    #[test]
+    fn fill_ratio_test() {
+        let empty_tree: BTree<int, ()> = BTree::with_degree(3);
+        assert_eq!(empty_tree.fill_ratio(), 0.0);
+        let full_tree = BTree::from_sorted(range(0, 5).map(|i| (i, ())).collect(), 3);
+        assert_eq!(full_tree.fill_ratio(), 1.0);
+        let pairs: ~[(int, ())] = range(0, 1000).map(|i| (i, ())).collect();
+        let bulk = BTree::from_sorted(pairs.clone(), 3);
+        let mut incremental = BTree::with_degree(3);
+        for &(k, v) in pairs.iter() {
+            incremental.insert(k, v);
+        }
+        assert!(bulk.fill_ratio() > incremental.fill_ratio());
+        assert!(bulk.fill_ratio() <= 1.0);
+        assert!(incremental.fill_ratio() > 0.0);
+    }
+
+    #[test]
+    fn validate_random_test() {
+        let mut rng = rand::task_rng();
+        for md in range(2u, 5) {
+            let mut new_tree = BTree::with_degree(md);
+            assert!(new_tree.validate());
+            for _ in range(0, 1000) {
+                let k = rng.gen_range(0, 10000);
+                if !new_tree.contains_key(&k) {
+                    new_tree.insert(k, ());
+                }
+                assert!(new_tree.validate());
+            }
+        }
+    }
+
+    #[test]
+    fn validate_bad_tree_test() {
+        //Keys out of order within a node.
+        let new_node = Node { elts: vec!(Elt { key: 2, value: () }, Elt { key: 1, value: () }),
+                              kind: Leaf };
+        assert!(!BTree { root: new_node, len: 2, height: 1, min_deg: 2,
+                         dup_policy: Overwrite }.validate());
+        //A child key on the wrong side of its separator.
+        let new_node = Node { elts: vec!(Elt { key: 3, value: () }),
+                              kind: Branch(vec!(~Node { elts: vec!(Elt { key: 4, value: () }),
+                                                       kind: Leaf },
+                                               ~Node { elts: vec!(Elt { key: 5, value: () }),
+                                                       kind: Leaf }), 3)};
+        assert!(!BTree { root: new_node, len: 3, height: 2, min_deg: 2,
+                         dup_policy: Overwrite }.validate());
+        //Leaves at different depths.
+        let deep_node = Node { elts: vec!(Elt { key: 5, value: () }),
+                               kind: Branch(vec!(~Node { elts: vec!(Elt { key: 4, value: () }),
+                                                        kind: Leaf },
+                                                ~Node { elts: vec!(Elt { key: 6, value: () }),
+                                                        kind: Leaf }), 3)};
+        let new_node = Node { elts: vec!(Elt { key: 3, value: () }),
+                              kind: Branch(vec!(~Node { elts: vec!(Elt { key: 1, value: () }),
+                                                       kind: Leaf },
+                                               ~deep_node), 5)};
+        assert!(!BTree { root: new_node, len: 5, height: 2, min_deg: 2,
+                         dup_policy: Overwrite }.validate());
+        //An underfull node below the root.
+        let new_node = Node { elts: vec!(Elt { key: 3, value: () }),
+                              kind: Branch(vec!(~Node { elts: vec!(), kind: Leaf },
+                                               ~Node { elts: vec!(Elt { key: 5, value: () }),
+                                                       kind: Leaf }), 2)};
+        assert!(!BTree { root: new_node, len: 2, height: 2, min_deg: 2,
+                         dup_policy: Overwrite }.validate());
+    }
+
+    #[test]
+    fn find_nearest_test() {
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.find_nearest(&5), None);
+        for &k in [0, 10, 25, 40, 41, 100].iter() {
+            new_tree.insert(k, k.to_str());
+        }
+        assert_eq!(new_tree.find_nearest(&13), Some((&10, &~"10")));
+        assert_eq!(new_tree.find_nearest(&20), Some((&25, &~"25")));
+        assert_eq!(new_tree.find_nearest(&25), Some((&25, &~"25")));
+        //A tie goes to the smaller key.
+        assert_eq!(new_tree.find_nearest(&5), Some((&0, &~"0")));
+        assert_eq!(new_tree.find_nearest(&70), Some((&41, &~"41")));
+        assert_eq!(new_tree.find_nearest(&71), Some((&100, &~"100")));
+        assert_eq!(new_tree.find_nearest(&-50), Some((&0, &~"0")));
+        assert_eq!(new_tree.find_nearest(&500), Some((&100, &~"100")));
+
+        let mut unsigned_tree = BTree::with_degree(3);
+        for k in range(1u, 20) {
+            unsigned_tree.insert(k * k, ());
+        }
+        assert_eq!(unsigned_tree.find_nearest(&0), Some((&1, &())));
+        assert_eq!(unsigned_tree.find_nearest(&30), Some((&25, &())));
+        assert_eq!(unsigned_tree.find_nearest(&31), Some((&36, &())));
+    }
+
+    #[test]
+    fn floor_ceiling_test() {
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.floor(&5), None);
+        assert_eq!(new_tree.ceiling(&5), None);
+        //Keys 0, 10, 20, ..., 490 in a tree several levels deep.
+        for i in range(0, 50) {
+            new_tree.insert(i * 10, i);
+        }
+        assert!(new_tree.height() > 2);
+        for i in range(0, 50) {
+            let k = i * 10;
+            assert_eq!(new_tree.floor(&k), Some((&k, &i)));
+            assert_eq!(new_tree.ceiling(&k), Some((&k, &i)));
+            assert_eq!(new_tree.floor(&(k + 5)), Some((&k, &i)));
+            assert_eq!(new_tree.ceiling(&(k - 5)), Some((&k, &i)));
+        }
+        assert_eq!(new_tree.floor(&-1), None);
+        assert_eq!(new_tree.ceiling(&-1), Some((&0, &0)));
+        assert_eq!(new_tree.floor(&500), Some((&490, &49)));
+        assert_eq!(new_tree.ceiling(&491), None);
+    }
+
+    #[test]
+    fn predecessor_successor_test() {
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.predecessor(&5), None);
+        assert_eq!(new_tree.successor(&5), None);
+        for i in range(0, 50) {
+            new_tree.insert(i * 10, i);
+        }
+        assert!(new_tree.height() > 2);
+        for i in range(1, 49) {
+            let k = i * 10;
+            //Present keys, which may be separators in branches.
+            assert_eq!(new_tree.predecessor(&k), Some((&(k - 10), &(i - 1))));
+            assert_eq!(new_tree.successor(&k), Some((&(k + 10), &(i + 1))));
+            //Absent keys between two present ones.
+            assert_eq!(new_tree.predecessor(&(k + 5)), Some((&k, &i)));
+            assert_eq!(new_tree.successor(&(k - 5)), Some((&k, &i)));
+        }
+        assert_eq!(new_tree.predecessor(&0), None);
+        assert_eq!(new_tree.successor(&0), Some((&10, &1)));
+        assert_eq!(new_tree.predecessor(&490), Some((&480, &48)));
+        assert_eq!(new_tree.successor(&490), None);
+        assert_eq!(new_tree.predecessor(&-5), None);
+        assert_eq!(new_tree.successor(&-5), Some((&0, &0)));
+        assert_eq!(new_tree.predecessor(&1000), Some((&490, &49)));
+        assert_eq!(new_tree.successor(&1000), None);
+    }
+
+    #[test]
+    fn equal_range_test() {
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.equal_range(&5), (None, None));
+        for i in range(0, 50) {
+            new_tree.insert(i * 10, i);
+        }
+        assert!(new_tree.height() > 2);
+        for k in range(-5, 500) {
+            assert_eq!(new_tree.equal_range(&k), (new_tree.find(&k), new_tree.successor(&k)));
+        }
+        assert_eq!(new_tree.equal_range(&240), (Some(&24), Some((&250, &25))));
+        assert_eq!(new_tree.equal_range(&245), (None, Some((&250, &25))));
+        assert_eq!(new_tree.equal_range(&490), (Some(&49), None));
+    }
+
+    #[test]
+    fn nth_test() {
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.nth(0), None);
+        for i in range(0, 100) {
+            new_tree.insert(i * 3, i);
+        }
+        assert_eq!(new_tree.nth(0), Some((&0, &0)));
+        assert_eq!(new_tree.nth(50), Some((&150, &50)));
+        assert_eq!(new_tree.nth(99), Some((&297, &99)));
+        assert_eq!(new_tree.nth(100), None);
+        assert_eq!(new_tree.nth(50), new_tree.iter().nth(50));
+    }
+
+    #[test]
+    fn select_test() {
+        let mut rng = rand::task_rng();
+        let mut new_tree = BTree::with_degree(3);
+        assert_eq!(new_tree.select(0), None);
+        for k in rng.shuffle(range(0, 500).collect::<~[int]>()).move_iter() {
+            new_tree.insert(k * 2, k);
+        }
+        assert_eq!(new_tree.select(0), Some((&0, &0)));
+        assert_eq!(new_tree.select(250), Some((&500, &250)));
+        assert_eq!(new_tree.select(499), Some((&998, &499)));
+        assert_eq!(new_tree.select(500), None);
+        for (i, (k, _)) in new_tree.iter().enumerate() {
+            assert_eq!(new_tree.select(i).map(|(k, _)| *k), Some(*k));
+        }
+        //The cached sizes must survive removals as well.
+        for k in range(0, 250) {
+            new_tree.remove(&(k * 4));
+        }
+        assert!(new_tree.validate());
+        assert_eq!(new_tree.select(0), Some((&2, &1)));
+        assert_eq!(new_tree.select(249), Some((&998, &499)));
+        assert_eq!(new_tree.select(250), None);
+    }
+
+    #[test]
+    fn rank_test() {
+        let mut rng = rand::task_rng();
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.rank(&5), 0);
+        for _ in range(0, 300) {
+            let k = rng.gen_range(0, 1000);
+            if !new_tree.contains_key(&k) {
+                new_tree.insert(k, ());
+            }
+        }
+        for k in range(-1, 1001) {
+            let expected = new_tree.keys().count(|key| *key < k);
+            assert_eq!(new_tree.rank(&k), expected);
+        }
+    }
+
+    #[test]
+    fn position_of_test() {
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.position_of(&0), None);
+        for i in range(0, 200) {
+            new_tree.insert(i * 5, ());
+        }
+        assert_eq!(new_tree.position_of(&0), Some(0));
+        assert_eq!(new_tree.position_of(&500), Some(100));
+        assert_eq!(new_tree.position_of(&995), Some(199));
+        assert_eq!(new_tree.position_of(&3), None);
+        assert_eq!(new_tree.position_of(&-5), None);
+        assert_eq!(new_tree.position_of(&1000), None);
+        for (i, k) in new_tree.keys().enumerate() {
+            assert_eq!(new_tree.position_of(k), Some(i));
+        }
+    }
+
+    #[test]
+    fn count_matching_test() {
+        let mut rng = rand::task_rng();
+        let mut new_tree = BTree::with_degree(3);
+        assert_eq!(new_tree.count_matching(|_| true), 0);
+        let mut keys = ~[];
+        for _ in range(0, 300) {
+            let k = rng.gen_range(0, 1000);
+            if new_tree.insert(k, ()).is_none() {
+                keys.push(k);
+            }
+        }
+        for m in range(1, 10) {
+            let expected = keys.iter().count(|k| **k % m == 1);
+            assert_eq!(new_tree.count_matching(|k| *k % m == 1), expected);
+        }
+        assert_eq!(new_tree.count_matching(|_| true), keys.len());
+        assert_eq!(new_tree.count_matching(|_| false), 0);
+    }
+
+    #[test]
+    fn count_range_test() {
+        let mut rng = rand::task_rng();
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.count_range(&0, &10), 0);
+        for _ in range(0, 200) {
+            let k = rng.gen_range(0, 500);
+            if !new_tree.contains_key(&k) {
+                new_tree.insert(k, ());
+            }
+        }
+        for lo in range(-10, 510).filter(|k| *k % 7 == 0) {
+            for hi in range(-10, 510).filter(|k| *k % 11 == 0) {
+                let expected = new_tree.range(&lo, &hi).count(|_| true);
+                assert_eq!(new_tree.count_range(&lo, &hi), expected);
+            }
+        }
+        assert_eq!(new_tree.count_range(&-100, &1000), new_tree.len());
+        assert_eq!(new_tree.count_range(&250, &250), 0);
+        assert_eq!(new_tree.count_range(&300, &200), 0);
+    }
+
+    //Splits a tree holding the even keys from 0 to 198 at the given key,
+    //returning the keys left in it and the keys moved out.
+    fn split_off_keys(at: int) -> (~[int], ~[int]) {
+        let mut new_tree = BTree::with_degree(3);
+        for i in range(0, 100) {
+            new_tree.insert(i * 2, i);
+        }
+        let right = new_tree.split_off(&at);
+        assert!(new_tree.validate());
+        assert!(right.validate());
+        assert_eq!(right.min_deg, 3);
+        (new_tree.keys().map(|k| *k).collect(), right.keys().map(|k| *k).collect())
+    }
+
+    #[test]
+    fn split_off_test() {
+        //At a key in the tree.
+        let (left, right) = split_off_keys(100);
+        assert_eq!(left, range(0, 50).map(|i| i * 2).collect::<~[int]>());
+        assert_eq!(right, range(50, 100).map(|i| i * 2).collect::<~[int]>());
+        //Between two keys.
+        let (left, right) = split_off_keys(31);
+        assert_eq!(left, range(0, 16).map(|i| i * 2).collect::<~[int]>());
+        assert_eq!(right, range(16, 100).map(|i| i * 2).collect::<~[int]>());
+        //Below the smallest key, which moves everything.
+        let (left, right) = split_off_keys(-1);
+        assert!(left.is_empty());
+        assert_eq!(right, range(0, 100).map(|i| i * 2).collect::<~[int]>());
+        //Above the largest key, which moves nothing.
+        let (left, right) = split_off_keys(1000);
+        assert_eq!(left, range(0, 100).map(|i| i * 2).collect::<~[int]>());
+        assert!(right.is_empty());
+    }
+
+    #[test]
+    fn merge_with_test() {
+        let mut counts_a = BTree::with_degree(2);
+        for w in ["a", "b", "c", "a", "d"].iter() {
+            *counts_a.find_or_insert(w.to_owned(), 0) += 1;
+        }
+        let mut counts_b = BTree::with_degree(2);
+        for w in ["b", "a", "e", "c", "a"].iter() {
+            *counts_b.find_or_insert(w.to_owned(), 0) += 1;
+        }
+        counts_a.merge_with(counts_b, |n, m| *n += m);
+        assert!(counts_a.validate());
+        let merged: ~[(~str, int)] = counts_a.move_iter().collect();
+        assert_eq!(merged, ~[(~"a", 4), (~"b", 2), (~"c", 2), (~"d", 1), (~"e", 1)]);
+
+        let mut new_tree = BTree::with_degree(3);
+        let mut other = BTree::with_degree(2);
+        for i in range(0, 100) {
+            new_tree.insert(i * 2, 1);
+            other.insert(i * 3, 10);
+        }
+        new_tree.merge_with(other, |n, m| *n += m);
+        assert!(new_tree.validate());
+        assert_eq!(new_tree.min_deg(), 3);
+        for (k, v) in new_tree.iter() {
+            let expected = (if *k % 2 == 0 && *k < 200 { 1 } else { 0 }) +
+                           (if *k % 3 == 0 && *k < 300 { 10 } else { 0 });
+            assert_eq!(*v, expected);
+        }
+        assert_eq!(new_tree.len(), 200 - 34);
+    }
+
+    #[test]
+    fn append_disjoint_test() {
+        let mut new_tree = BTree::with_degree(2);
+        let mut other = BTree::with_degree(3);
+        for i in range(0, 50) {
+            new_tree.insert(i, i);
+            other.insert(i + 50, i + 50);
+        }
+        new_tree.append(other);
+        assert!(new_tree.validate());
+        assert_eq!(new_tree.len(), 100);
+        assert_eq!(new_tree.min_deg, 2);
+        let keys: ~[int] = new_tree.keys().map(|k| *k).collect();
+        assert_eq!(keys, range(0, 100).collect::<~[int]>());
+    }
+
+    #[test]
+    fn append_splice_test() {
+        //Every pairing of sizes, so that either tree can be the taller
+        //and either can hold the greater keys.
+        for md in range(2u, 5) {
+            for n in range(0, 40) {
+                for m in range(0, 40) {
+                    let mut low = BTree::with_dup_policy(md, KeepFirst);
+                    let mut high = BTree::with_degree(md);
+                    for i in range(0, n) {
+                        low.insert(i, i);
+                    }
+                    for i in range(0, m) {
+                        high.insert(n + i, n + i);
+                    }
+                    let mut lower = low.clone();
+                    lower.append(high.clone());
+                    assert!(lower.validate());
+                    assert_eq!(lower.len(), n + m);
+                    assert_eq!(lower.dup_policy(), KeepFirst);
+                    assert!(lower.iter().enumerate().all(|(i, (k, v))| *k == i && *v == i));
+                    high.append(low);
+                    assert!(high.validate());
+                    assert_eq!(high.len(), n + m);
+                    assert_eq!(high.dup_policy(), Overwrite);
+                    assert!(high.iter().enumerate().all(|(i, (k, v))| *k == i && *v == i));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn merge_with_small_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 500) {
+            new_tree.insert(i * 2, 1);
+        }
+        let mut other = BTree::with_degree(2);
+        for i in range(0, 10) {
+            other.insert(i * 7, 10);
+        }
+        new_tree.merge_with(other, |n, m| *n += m);
+        assert!(new_tree.validate());
+        //Multiples of 14 below 70 are in both trees.
+        assert_eq!(new_tree.len(), 500 + 10 - 5);
+        assert_eq!(new_tree.find(&14), Some(&11));
+        assert_eq!(new_tree.find(&21), Some(&10));
+        assert_eq!(new_tree.find(&22), Some(&1));
+    }
+
+    #[test]
+    fn append_overlapping_test() {
+        let mut new_tree = BTree::with_degree(2);
+        let mut other = BTree::with_degree(2);
+        for i in range(0, 60) {
+            new_tree.insert(i * 2, ~"self");
+        }
+        for i in range(0, 40) {
+            other.insert(i * 3, ~"other");
+        }
+        new_tree.append(other);
+        assert!(new_tree.validate());
+        //Multiples of 6 below 120 are in both trees.
+        assert_eq!(new_tree.len(), 60 + 40 - 20);
+        for (k, v) in new_tree.iter() {
+            if *k % 3 == 0 {
+                assert_eq!(*v, ~"other");
+            }
+            else {
+                assert_eq!(*v, ~"self");
+            }
+        }
+    }
+
+    #[test]
+    fn remove_range_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 100) {
+            new_tree.insert(i, i.to_str());
+        }
+        assert_eq!(new_tree.remove_range(&30, &60), 30);
+        assert_eq!(new_tree.len(), 70);
+        assert!(new_tree.validate());
+        assert_eq!(new_tree.find(&29), Some(&~"29"));
+        assert!(!new_tree.contains_key(&30));
+        assert!(!new_tree.contains_key(&59));
+        assert_eq!(new_tree.find(&60), Some(&~"60"));
+        assert_eq!(new_tree.successor(&29), Some((&60, &~"60")));
+        assert_eq!(new_tree.remove_range(&30, &60), 0);
+        assert_eq!(new_tree.remove_range(&80, &70), 0);
+        assert_eq!(new_tree.remove_range(&-10, &10), 10);
+        assert_eq!(new_tree.remove_range(&95, &200), 5);
+        let keys: ~[int] = new_tree.keys().map(|k| *k).collect();
+        let expected: ~[int] = range(10, 30).chain(range(60, 95)).collect();
+        assert_eq!(keys, expected);
+        assert!(new_tree.validate());
+    }
+
+    #[test]
+    fn truncate_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 20) {
+            new_tree.insert(i * 7 % 20, i);
+        }
+        new_tree.truncate(5);
+        assert_eq!(new_tree.len(), 5);
+        assert!(new_tree.validate());
+        let keys: ~[int] = new_tree.keys().map(|k| *k).collect();
+        assert_eq!(keys, ~[0, 1, 2, 3, 4]);
+        new_tree.truncate(10);
+        assert_eq!(new_tree.len(), 5);
+        new_tree.truncate(0);
+        assert!(new_tree.is_empty());
+        assert!(new_tree.validate());
+    }
+
+    #[test]
+    fn retain_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 200) {
+            new_tree.insert(i, i);
+        }
+        new_tree.retain(|k, v| {
+            *v *= 10;
+            *k % 2 == 0
+        });
+        assert!(new_tree.validate());
+        assert_eq!(new_tree.len(), 100);
+        let entries: ~[(int, int)] = new_tree.iter().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(entries, range(0, 100).map(|i| (i * 2, i * 20)).collect::<~[(int, int)]>());
+        new_tree.retain(|_, _| false);
+        assert!(new_tree.is_empty());
+        assert!(new_tree.validate());
+    }
+
+    #[test]
+    fn insert_if_absent_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 30) {
+            assert!(new_tree.insert_if_absent(i, i.to_str()));
+        }
+        for i in range(0, 30) {
+            assert!(!new_tree.insert_if_absent(i, ~"new"));
+        }
+        assert_eq!(new_tree.len(), 30);
+        assert!(new_tree.validate());
+        for i in range(0, 30) {
+            assert_eq!(new_tree.find(&i), Some(&i.to_str()));
+        }
+    }
+
+    #[test]
+    fn find_or_insert_test() {
+        let mut new_tree = BTree::with_degree(2);
+        *new_tree.find_or_insert(5, 50) += 1;
+        assert_eq!(new_tree.find(&5), Some(&51));
+        assert_eq!(*new_tree.find_or_insert(5, 0), 51);
+        assert_eq!(new_tree.len(), 1);
+        let mut calls = 0;
+        assert_eq!(*new_tree.find_or_insert_with(5, || { calls += 1; 0 }), 51);
+        assert_eq!(calls, 0);
+        assert_eq!(*new_tree.find_or_insert_with(6, || { calls += 1; 60 }), 60);
+        assert_eq!(calls, 1);
+        assert_eq!(new_tree.len(), 2);
+    }
+
+    //Counting with find_or_insert must not duplicate keys, whether they
+    //end up in leaves, as separators, or as the medians of split nodes.
+    #[test]
+    fn find_or_insert_counter_test() {
+        let mut rng = rand::task_rng();
+        let mut new_tree = BTree::with_degree(2);
+        let mut counts = [0u, ..50];
+        for _ in range(0, 2000) {
+            let k = rng.gen_range(0u, 50);
+            counts[k] += 1;
+            *new_tree.find_or_insert_with(k, || 0u) += 1;
+            assert!(new_tree.validate());
+        }
+        for (k, v) in new_tree.iter() {
+            assert_eq!(*v, counts[*k]);
+        }
+        assert_eq!(new_tree.len(), counts.iter().count(|&n| n > 0));
+    }
+
+    #[test]
+    fn pop_min_test() {
+        let mut rng = rand::task_rng();
+        let mut new_tree = BTree::with_degree(2);
+        assert_eq!(new_tree.pop_min(), None);
+        for k in rng.shuffle(range(0, 300).collect::<~[int]>()).move_iter() {
+            new_tree.insert(k, -k);
+        }
+        for k in range(0, 300) {
+            assert_eq!(new_tree.pop_min(), Some((k, -k)));
+            assert!(new_tree.validate());
+        }
+        assert!(new_tree.is_empty());
+        assert_eq!(new_tree.pop_min(), None);
+    }
+
+    #[test]
+    fn pop_max_test() {
+        let mut new_tree = BTree::with_degree(3);
+        assert_eq!(new_tree.pop_max(), None);
+        for k in range(0, 300) {
+            new_tree.insert(k, -k);
+        }
+        for k in range(0, 300).rev() {
+            assert_eq!(new_tree.pop_max(), Some((k, -k)));
+            assert!(new_tree.validate());
+        }
+        assert!(new_tree.is_empty());
+        assert_eq!(new_tree.height(), 1);
+    }
+
+    #[test]
+    fn index_test() {
+        let mut new_tree = BTree::with_degree(2);
+        for i in range(0, 30) {
+            new_tree.insert(i, i.to_str());
+        }
+        assert_eq!(new_tree[0], ~"0");
+        assert_eq!(new_tree[17], ~"17");
+        assert_eq!(new_tree[29], ~"29");
+    }
+
+    #[test]
+    #[should_fail]
+    fn index_missing_test() {
+        let mut new_tree = BTree::with_degree(2);
+        new_tree.insert(1, 1);
+        new_tree[2];
+    }
+
+    #[test]
+    fn remove_leaf_test() {
+        let mut new_tree = BTree::new(1, ~"a", 2);
+        new_tree.insert(2, ~"b");
+        new_tree.insert(3, ~"c");
+        assert_eq!(new_tree.remove(&2), Some(~"b"));
+        assert_eq!(new_tree.remove(&2), None);
+        assert_eq!(new_tree.find(&1), Some(&~"a"));
+        assert_eq!(new_tree.find(&3), Some(&~"c"));
+    }
+
+    //Removing a separator whose children are both minimal merges them,
+    //and the empty root is replaced by the merged child.
+    #[test]
+    fn remove_merge_test() {
+        let new_node = Node { elts: vec!(Elt { key: 2, value: ~"b" }),
+                              kind: Branch(vec!(~Node { elts: vec!(Elt { key: 1, value: ~"a" }),
+                                                       kind: Leaf },
+                                               ~Node { elts: vec!(Elt { key: 3, value: ~"c" }),
+                                                       kind: Leaf }), 3)};
+        let mut new_tree = BTree { root: new_node, len: 3, height: 2, min_deg: 2,
+                                   dup_policy: Overwrite };
+        assert_eq!(new_tree.remove(&2), Some(~"b"));
+        assert!(new_tree.root.is_leaf());
+        assert_eq!(new_tree.root.elts.len(), 2);
+        assert_eq!(new_tree.root.elts.get(0).key, 1);
+        assert_eq!(new_tree.root.elts.get(1).key, 3);
+    }
+
+    //Removing from a minimal leaf borrows from its fuller sibling.
+    #[test]
+    fn remove_borrow_test() {
+        let new_node = Node { elts: vec!(Elt { key: 2, value: ~"b" }),
+                              kind: Branch(vec!(~Node { elts: vec!(Elt { key: 1, value: ~"a" }),
+                                                       kind: Leaf },
+                                               ~Node { elts: vec!(Elt { key: 3, value: ~"c" },
+                                                               Elt { key: 4, value: ~"d" },
+                                                               Elt { key: 5, value: ~"e" }),
+                                                       kind: Leaf }), 5)};
+        let mut new_tree = BTree { root: new_node, len: 5, height: 2, min_deg: 2,
+                                   dup_policy: Overwrite };
+        assert_eq!(new_tree.remove(&1), Some(~"a"));
+        assert_eq!(new_tree.root.elts.get(0).key, 3);
+        assert_eq!(new_tree.root.kids().get(0).elts.get(0).key, 2);
+        assert!(new_tree.validate());
+    }
+
+    #[test]
+    fn remove_test_many() {
+        let mut new_tree = BTree::new(0, 0, 3);
+        for i in range(1, 200) {
+            new_tree.insert(i * 37 % 200, i);
+        }
+        for i in range(0, 100) {
+            let k = i * 2;
+            assert!(new_tree.remove(&k).is_some());
+            assert!(new_tree.find(&k).is_none());
+            assert!(new_tree.validate());
+        }
+        for i in range(0, 100) {
+            assert!(new_tree.contains_key(&(i * 2 + 1)));
+        }
+    }
+
+
+}
+
+#[cfg(test)]
+mod test_btree_set {
+    use super::BTreeSet;
+
+    #[test]
+    fn insert_dedup_test() {
+        let mut set = BTreeSet::with_degree(2);
+        assert!(set.is_empty());
+        for i in range(0, 100) {
+            assert!(set.insert(i));
+        }
+        for i in range(0, 100) {
+            assert!(!set.insert(i));
+        }
+        assert_eq!(set.len(), 100);
+        assert!(set.contains(&42));
+        assert!(!set.contains(&100));
+    }
+
+    #[test]
+    fn remove_test() {
+        let mut set = BTreeSet::with_degree(2);
+        for i in range(0, 50) {
+            set.insert(i);
+        }
+        assert!(set.remove(&10));
+        assert!(!set.remove(&10));
+        assert!(!set.remove(&50));
+        assert!(!set.contains(&10));
+        assert_eq!(set.len(), 49);
+    }
+
+    #[test]
+    fn iter_test() {
+        let mut set = BTreeSet::with_degree(2);
+        for &k in [5, 3, 9, 1, 7, 3, 5, 0, 8].iter() {
+            set.insert(k);
+        }
+        let keys: ~[int] = set.iter().map(|k| *k).collect();
+        assert_eq!(keys, ~[0, 1, 3, 5, 7, 8, 9]);
+    }
+
+    //Checks each set operation against a brute-force filter over the
+    //candidate keys.
+    fn check_set_ops(xs: &[int], ys: &[int]) {
+        let a: BTreeSet<int> = xs.iter().map(|x| *x).collect();
+        let b: BTreeSet<int> = ys.iter().map(|y| *y).collect();
+        let difference: ~[int] = a.difference(&b).map(|k| *k).collect();
+        let expected: ~[int] = range(-1, 101)
+            .filter(|k| xs.contains(k) && !ys.contains(k)).collect();
+        assert_eq!(difference, expected);
+        let intersection: ~[int] = a.intersection(&b).map(|k| *k).collect();
+        let expected: ~[int] = range(-1, 101)
+            .filter(|k| xs.contains(k) && ys.contains(k)).collect();
+        assert_eq!(intersection, expected);
+        let union: ~[int] = a.union(&b).map(|k| *k).collect();
+        let expected: ~[int] = range(-1, 101)
+            .filter(|k| xs.contains(k) || ys.contains(k)).collect();
+        assert_eq!(union, expected);
+    }
+
+    #[test]
+    fn set_ops_test() {
+        check_set_ops(&[], &[]);
+        check_set_ops(&[1, 2, 3], &[]);
+        check_set_ops(&[], &[1, 2, 3]);
+        check_set_ops(&[1, 3, 5, 7], &[2, 4, 6, 8]);
+        check_set_ops(&[5, 1, 9, 3], &[3, 9, 11, 0, -1]);
+        check_set_ops(&[1, 2, 3], &[1, 2, 3]);
+        let evens = range(0, 50).map(|i| i * 2).collect::<~[int]>();
+        let threes = range(0, 34).map(|i| i * 3).collect::<~[int]>();
+        check_set_ops(evens.as_slice(), threes.as_slice());
+        check_set_ops(threes.as_slice(), evens.as_slice());
+    }
+
+    #[test]
+    fn from_iterator_test() {
+        let xs = ~[4, 2, 4, 1, 2, 8];
+        let set: BTreeSet<int> = xs.iter().map(|x| *x).collect();
+        assert_eq!(set.len(), 4);
+        let keys: ~[int] = set.iter().map(|k| *k).collect();
+        assert_eq!(keys, ~[1, 2, 4, 8]);
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    extern crate test;
+    use self::test::BenchHarness;
+    use super::{BTree, find_recursive};
+    use deque::bench::{insert_rand_n, insert_seq_n, find_rand_n, find_seq_n};
+    use std::{vec, rand};
+    use std::rand::Rng;
+
+    // Insert rand
+    #[bench]
+    pub fn insert_rand_100(bh: &mut BenchHarness) {
+        let mut m : BTree<uint,uint> = BTree::with_degree(6);
+        insert_rand_n(100, &mut m, bh);
+    }
+
+    #[bench]
+    pub fn insert_rand_10_000(bh: &mut BenchHarness) {
+        let mut m : BTree<uint,uint> = BTree::with_degree(6);
+        insert_rand_n(10_000, &mut m, bh);
+    }
+
+    // Insert seq
+    #[bench]
+    pub fn insert_seq_100(bh: &mut BenchHarness) {
+        let mut m : BTree<uint,uint> = BTree::with_degree(6);
+        insert_seq_n(100, &mut m, bh);
+    }
+
+    #[bench]
+    pub fn insert_seq_10_000(bh: &mut BenchHarness) {
+        let mut m : BTree<uint,uint> = BTree::with_degree(6);
+        insert_seq_n(10_000, &mut m, bh);
+    }
+
+    // Find rand
+    #[bench]
+    pub fn find_rand_100(bh: &mut BenchHarness) {
+        let mut m : BTree<uint,uint> = BTree::with_degree(6);
+        find_rand_n(100, &mut m, bh);
+    }
+
+    #[bench]
+    pub fn find_rand_10_000(bh: &mut BenchHarness) {
+        let mut m : BTree<uint,uint> = BTree::with_degree(6);
+        find_rand_n(10_000, &mut m, bh);
+    }
+
+    // Find seq
+    #[bench]
+    pub fn find_seq_100(bh: &mut BenchHarness) {
+        let mut m : BTree<uint,uint> = BTree::with_degree(6);
+        find_seq_n(100, &mut m, bh);
+    }
+
+    #[bench]
+    pub fn find_seq_10_000(bh: &mut BenchHarness) {
+        let mut m : BTree<uint,uint> = BTree::with_degree(6);
+        find_seq_n(10_000, &mut m, bh);
+    }
+
+    //Builds a tree of n keys, either 0 to n - 1 or drawn at random, and
+    //returns it along with the keys to look up, in the order to look them
+    //up, so that the iterative and recursive finds below see the same
+    //trees and the same lookups.
+    fn tree_and_keys(n: uint, random: bool) -> (BTree<uint, uint>, ~[uint]) {
+        let mut rng = rand::XorShiftRng::new();
+        let mut keys = if random {
+            vec::from_fn(n, |_| rng.gen::<uint>() % n)
+        } else {
+            vec::from_fn(n, |i| i)
+        };
+        let mut tree = BTree::with_degree(6);
+        for k in keys.iter() {
+            tree.insert(*k, 1);
+        }
+        if random {
+            rng.shuffle_mut(keys);
+        }
+        (tree, keys)
+    }
+
+    fn find_iterative_n(n: uint, random: bool, bh: &mut BenchHarness) {
+        let (tree, keys) = tree_and_keys(n, random);
+        let mut i = 0;
+        bh.iter(|| {
+            tree.find(&(keys[i]));
+            i = (i + 1) % n;
+        })
+    }
+
+    fn find_recursive_n(n: uint, random: bool, bh: &mut BenchHarness) {
+        let (tree, keys) = tree_and_keys(n, random);
+        let mut i = 0;
+        bh.iter(|| {
+            find_recursive(&tree.root, &(keys[i]));
+            i = (i + 1) % n;
+        })
+    }
+
+    // Find iterative vs. recursive, rand
+    #[bench]
+    pub fn find_iterative_rand_100(bh: &mut BenchHarness) {
+        find_iterative_n(100, true, bh);
+    }
+
+    #[bench]
+    pub fn find_recursive_rand_100(bh: &mut BenchHarness) {
+        find_recursive_n(100, true, bh);
+    }
+
+    #[bench]
+    pub fn find_iterative_rand_10_000(bh: &mut BenchHarness) {
+        find_iterative_n(10_000, true, bh);
+    }
+
+    #[bench]
+    pub fn find_recursive_rand_10_000(bh: &mut BenchHarness) {
+        find_recursive_n(10_000, true, bh);
+    }
+
+    // Find iterative vs. recursive, seq
+    #[bench]
+    pub fn find_iterative_seq_100(bh: &mut BenchHarness) {
+        find_iterative_n(100, false, bh);
+    }
+
+    #[bench]
+    pub fn find_recursive_seq_100(bh: &mut BenchHarness) {
+        find_recursive_n(100, false, bh);
+    }
+
+    #[bench]
+    pub fn find_iterative_seq_10_000(bh: &mut BenchHarness) {
+        find_iterative_n(10_000, false, bh);
+    }
+
+    #[bench]
+    pub fn find_recursive_seq_10_000(bh: &mut BenchHarness) {
+        find_recursive_n(10_000, false, bh);
+    }
 }
@@ -26,7 +26,7 @@
 #[cfg(test)] extern crate test;
 
 pub use bitv::Bitv;
-pub use btree::BTree;
+pub use btree::{BTree, BTreeSet};
 pub use deque::Deque;
 pub use dlist::DList;
 pub use enum_set::EnumSet;
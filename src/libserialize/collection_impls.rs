@@ -16,7 +16,7 @@ use std::hash::{Hash, Hasher};
 
 use {Decodable, Encodable, Decoder, Encoder};
 use collections::{DList, RingBuf, TreeMap, TreeSet, Deque, HashMap, HashSet,
-                  TrieMap, TrieSet};
+                  TrieMap, TrieSet, BTree};
 use collections::enum_set::{EnumSet, CLike};
 
 impl<
@@ -104,6 +104,58 @@ impl<
     }
 }
 
+impl<
+    E: Encoder,
+    K: Encodable<E> + TotalOrd,
+    V: Encodable<E>
+> Encodable<E> for BTree<K, V> {
+    fn encode(&self, e: &mut E) {
+        e.emit_struct("BTree", 2, |e| {
+            e.emit_struct_field("min_deg", 0, |e| self.min_deg().encode(e));
+            e.emit_struct_field("entries", 1, |e| {
+                e.emit_map(self.len(), |e| {
+                    for (i, (key, val)) in self.iter().enumerate() {
+                        e.emit_map_elt_key(i, |e| key.encode(e));
+                        e.emit_map_elt_val(i, |e| val.encode(e));
+                    }
+                })
+            })
+        })
+    }
+}
+
+impl<
+    D: Decoder,
+    K: Decodable<D> + TotalOrd,
+    V: Decodable<D>
+> Decodable<D> for BTree<K, V> {
+    fn decode(d: &mut D) -> BTree<K, V> {
+        d.read_struct("BTree", 2, |d| {
+            let min_deg: uint = d.read_struct_field("min_deg", 0, |d| Decodable::decode(d));
+            if min_deg < 2 {
+                fail!("BTree::decode: a minimum degree of {} is less than 2", min_deg);
+            }
+            let pairs = d.read_struct_field("entries", 1, |d| {
+                d.read_map(|d, len| {
+                    let mut pairs = ~[];
+                    for i in range(0u, len) {
+                        let key = d.read_map_elt_key(i, |d| Decodable::decode(d));
+                        let val = d.read_map_elt_val(i, |d| Decodable::decode(d));
+                        pairs.push((key, val));
+                    }
+                    pairs
+                })
+            });
+            //The entries were encoded in key order, so the tree can be
+            //bulk-loaded, but the input may not have come from our encoder.
+            match BTree::try_from_sorted(pairs, min_deg) {
+                Ok(tree) => tree,
+                Err(msg) => fail!("BTree::decode: {}", msg)
+            }
+        })
+    }
+}
+
 impl<
     S: Encoder,
     T: Encodable<S> + Eq + TotalOrd
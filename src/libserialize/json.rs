@@ -1586,7 +1586,7 @@ mod tests {
     use super::{Encoder, Decoder, Error, Boolean, Number, List, String, Null,
                 PrettyEncoder, Object, Json, from_str};
     use std::io;
-    use collections::TreeMap;
+    use collections::{BTree, TreeMap};
 
     #[deriving(Eq, Encodable, Decodable, Show)]
     enum Animal {
@@ -2207,6 +2207,62 @@ mod tests {
         assert_eq!(map.pop(&~"b"), Some(Frog(~"Henry", 349)));
     }
 
+    #[test]
+    fn test_encode_decode_btree() {
+        let mut tree = BTree::with_degree(3);
+        for i in range(0, 30) {
+            tree.insert(i.to_str(), i);
+        }
+        let s = Encoder::str_encode(&tree);
+        let mut decoder = Decoder::new(from_str(s).unwrap());
+        let decoded: BTree<~str, int> = Decodable::decode(&mut decoder);
+        assert!(decoded == tree);
+        assert_eq!(decoded.min_deg(), 3);
+
+        let empty: BTree<~str, int> = BTree::with_degree(2);
+        let s = Encoder::str_encode(&empty);
+        assert_eq!(s, ~"{\"min_deg\":2,\"entries\":{}}");
+        let mut decoder = Decoder::new(from_str(s).unwrap());
+        let decoded: BTree<~str, int> = Decodable::decode(&mut decoder);
+        assert!(decoded.is_empty());
+    }
+
+    //A key whose order is the reverse of its string's, so that a JSON object
+    //(whose members come out sorted by string) decodes in descending order.
+    #[deriving(Eq, TotalEq)]
+    struct RevKey(~str);
+
+    impl Ord for RevKey {
+        fn lt(&self, other: &RevKey) -> bool { self.cmp(other) == Less }
+    }
+
+    impl TotalOrd for RevKey {
+        fn cmp(&self, other: &RevKey) -> Ordering {
+            let (&RevKey(ref a), &RevKey(ref b)) = (self, other);
+            b.cmp(a)
+        }
+    }
+
+    impl<D: ::Decoder> Decodable<D> for RevKey {
+        fn decode(d: &mut D) -> RevKey { RevKey(d.read_str()) }
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_decode_btree_unsorted() {
+        let s = "{\"min_deg\":2,\"entries\":{\"a\":0,\"b\":1}}";
+        let mut decoder = Decoder::new(from_str(s).unwrap());
+        let _: BTree<RevKey, int> = Decodable::decode(&mut decoder);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_decode_btree_bad_min_deg() {
+        let s = "{\"min_deg\":1,\"entries\":{}}";
+        let mut decoder = Decoder::new(from_str(s).unwrap());
+        let _: BTree<~str, int> = Decodable::decode(&mut decoder);
+    }
+
     #[test]
     fn test_multiline_errors() {
         assert_eq!(from_str("{\n  \"foo\":\n \"bar\""),